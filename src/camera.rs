@@ -0,0 +1,134 @@
+use std::{fs::{self, File}, path::Path};
+
+use crate::core::{bus::mapper::{SENSOR_HEIGHT, SENSOR_SIZE, SENSOR_WIDTH}, error::EmuError};
+
+/**
+ * Loads a PNG or PGM (P2/P5) file as a Game Boy Camera image. It's converted
+ * to grayscale, center-cropped to the sensor's aspect ratio and scaled to fit.
+ */
+pub fn load_image(path: impl AsRef<Path>) -> Result<Vec<u8>, EmuError> {
+  let path = path.as_ref();
+  let is_png = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+  let (width, height, pixels) = if is_png {
+    decode_png(path)?
+  } else {
+    decode_pgm(&fs::read(path)?)?
+  };
+  if width == 0 || height == 0 { return Err(EmuError::BadImage("Image is empty".into())); }
+  Ok(fit(width, height, &pixels))
+}
+
+/**
+ * Procedural image for when there's no camera input: a gradient behind a
+ * checkerboard frame and a dark disc, so shading and orientation are easy to
+ * judge.
+ */
+pub fn test_pattern() -> Vec<u8> {
+  let mut image = vec![0; SENSOR_SIZE];
+  let (cx, cy) = (SENSOR_WIDTH as i32 / 2, SENSOR_HEIGHT as i32 / 2);
+  for y in 0..SENSOR_HEIGHT {
+    for x in 0..SENSOR_WIDTH {
+      let (dx, dy) = (x as i32 - cx, y as i32 - cy);
+      let border = x < 8 || y < 8 || x >= SENSOR_WIDTH - 8 || y >= SENSOR_HEIGHT - 8;
+      image[y * SENSOR_WIDTH + x] = if border {
+        if (x / 8 + y / 8) % 2 == 0 { 0x00 } else { 0xFF }
+      } else if dx * dx + dy * dy < 24 * 24 {
+        0x20
+      } else {
+        (x * 0xFF / SENSOR_WIDTH) as u8
+      };
+    }
+  }
+  image
+}
+
+fn decode_png(path: &Path) -> Result<(usize, usize, Vec<u8>), EmuError> {
+  let bad_image = |err: png::DecodingError| EmuError::BadImage(err.to_string());
+  let mut decoder = png::Decoder::new(File::open(path)?);
+  decoder.set_transformations(png::Transformations::normalize_to_color8());
+  let mut reader = decoder.read_info().map_err(bad_image)?;
+  let mut buffer = vec![0; reader.output_buffer_size()];
+  let info = reader.next_frame(&mut buffer).map_err(bad_image)?;
+  let channels = info.color_type.samples();
+  let pixels = buffer[..info.buffer_size()]
+    .chunks_exact(info.line_size)
+    .flat_map(|line| line[..info.width as usize * channels].chunks_exact(channels))
+    .map(|pixel| match pixel {
+      [r, g, b, ..] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+      [gray, ..] => *gray,
+      [] => unreachable!(),
+    })
+    .collect();
+  Ok((info.width as usize, info.height as usize, pixels))
+}
+
+/**
+ * Netpbm grayscale, either plain (P2) or raw (P5), with samples of up to 16
+ * bits.
+ */
+fn decode_pgm(data: &[u8]) -> Result<(usize, usize, Vec<u8>), EmuError> {
+  let bad_image = || EmuError::BadImage("Not a PNG or PGM image".into());
+  let raw = match data.get(0..2) {
+    Some(b"P2") => false,
+    Some(b"P5") => true,
+    _ => return Err(bad_image()),
+  };
+  let mut pos = 2;
+  let (width, height, max) = match (
+    pgm_number(data, &mut pos),
+    pgm_number(data, &mut pos),
+    pgm_number(data, &mut pos),
+  ) {
+    (Some(width), Some(height), Some(max)) if (1..=0xFFFF).contains(&max) => (width, height, max),
+    _ => return Err(bad_image()),
+  };
+  let size = width.checked_mul(height).ok_or_else(bad_image)?;
+  let samples: Vec<usize> = if raw {
+    // A single whitespace byte separates the header from the samples
+    let sample_size = if max > 0xFF { 2 } else { 1 };
+    let start = pos + 1;
+    let end = size.checked_mul(sample_size).and_then(|len| start.checked_add(len));
+    let bytes = end.and_then(|end| data.get(start..end)).ok_or_else(bad_image)?;
+    bytes.chunks_exact(sample_size)
+      .map(|sample| sample.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+      .collect()
+  } else {
+    (0..size).map(|_| pgm_number(data, &mut pos)).collect::<Option<_>>().ok_or_else(bad_image)?
+  };
+  let pixels = samples.iter().map(|&sample| (sample.min(max) * 0xFF / max) as u8).collect();
+  Ok((width, height, pixels))
+}
+
+/**
+ * Next decimal number at or after `pos`, skipping whitespace and comments.
+ */
+fn pgm_number(data: &[u8], pos: &mut usize) -> Option<usize> {
+  loop {
+    match data.get(*pos)? {
+      b'#' => while data.get(*pos).is_some_and(|&byte| byte != b'\n') { *pos += 1; },
+      byte if byte.is_ascii_whitespace() => *pos += 1,
+      _ => break,
+    }
+  }
+  let start = *pos;
+  while data.get(*pos).is_some_and(u8::is_ascii_digit) { *pos += 1; }
+  std::str::from_utf8(&data[start..*pos]).ok()?.parse().ok()
+}
+
+fn fit(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+  let (crop_width, crop_height) = if width * SENSOR_HEIGHT > height * SENSOR_WIDTH {
+    (height * SENSOR_WIDTH / SENSOR_HEIGHT, height)
+  } else {
+    (width, width * SENSOR_HEIGHT / SENSOR_WIDTH)
+  };
+  let (left, top) = ((width - crop_width) / 2, (height - crop_height) / 2);
+  let mut image = vec![0; SENSOR_SIZE];
+  for y in 0..SENSOR_HEIGHT {
+    for x in 0..SENSOR_WIDTH {
+      let src_x = left + x * crop_width / SENSOR_WIDTH;
+      let src_y = top + y * crop_height / SENSOR_HEIGHT;
+      image[y * SENSOR_WIDTH + x] = pixels[src_y * width + src_x];
+    }
+  }
+  image
+}
@@ -1,92 +1,135 @@
-mod gamepad;
-mod timer;
+pub mod gamepad;
+pub mod serial;
+pub mod timer;
 pub mod oam;
+pub mod mapper;
 
-use memmap2::{Mmap, MmapMut};
+use self::{gamepad::{Gamepad, GamepadRegion}, mapper::Mapper, serial::Serial, timer::Timer, oam::Oam};
+use super::{cartridge::{Cartridge, MapperType}, cpu::Interrupt, error::EmuError, model::Model};
 
-use self::{gamepad::{Gamepad, GamepadRegion}, timer::Timer, oam::Oam};
-
-enum CartType { ROM, MBC1, MBC3, MBC5 }
+/**
+ * What the DMG boot ROM leaves in I/O registers that aren't zero. See
+ * `Model::post_boot_io` for other models.
+ */
+const POST_BOOT_IO: &[(u16, u8)] = &[
+  (0xFF02, 0x7E), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+  (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+  (0xFF16, 0x3F), (0xFF18, 0xFF), (0xFF19, 0xBF),
+  (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+  (0xFF20, 0xFF), (0xFF23, 0xBF), (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF26, 0xF1),
+  (0xFF40, 0x91), (0xFF41, 0x85), (0xFF46, 0xFF), (0xFF47, 0xFC), (0xFF48, 0xFF), (0xFF49, 0xFF),
+];
 
+#[derive(Clone)]
 pub struct Bus {
-  pub rom  : Mmap,
+  pub model     : Model,
+  pub cartridge : Cartridge,
+  pub mapper    : Box<dyn Mapper>,
+  /**
+   * Overlays the cartridge until 0xFF50 is written.
+   */
+  pub boot_rom  : Option<Vec<u8>>,
   pub vram : [u8; 0x2000],
-  pub sram : Option<MmapMut>,
   pub wram : [u8; 0x2000],
   pub oam  : Oam,
   pub io   : [u8; 0x80],
   pub hram : [u8; 0x7F],
   pub ie   : u8,
-  pub rom_bank  : u16,
-  pub sram_bank : u8,
   // TODO: write locks
   pub vram_lock        : bool,
   pub oam_lock         : bool,
   pub dma_transferring : bool,
   pub gamepad : Gamepad,
+  pub serial  : Serial,
   pub timer   : Timer,
-  cart_type : CartType,
+  /**
+   * Current T-state, kept up to date by `Emu` for hardware that only catches
+   * up when accessed.
+   */
+  pub t_state : u64,
+  /**
+   * Set by writes that may move the timer's or the mapper's next event.
+   */
+  pub reschedule : bool,
+  mapper_synced : u64,
 }
 
 impl Bus {
-  pub fn new(rom: Mmap, sram: Option<MmapMut>) -> Self {
-    let cart_type = match rom[0x147] {
-      0x00 => CartType::ROM,
-      0x01 => CartType::MBC1,
-      0x02 => CartType::MBC1,
-      0x03 => CartType::MBC1,
-      0x0F => CartType::MBC3,
-      0x10 => CartType::MBC3,
-      0x11 => CartType::MBC3,
-      0x12 => CartType::MBC3,
-      0x13 => CartType::MBC3,
-      0x19 => CartType::MBC5,
-      0x1A => CartType::MBC5,
-      0x1B => CartType::MBC5,
-      0x1C => CartType::MBC5,
-      0x1D => CartType::MBC5,
-      0x1E => CartType::MBC5,
-      _ => unimplemented!("Unsupported cartridge type"),
-    };
-    Self {
-      rom,
+  /**
+   * `sram` is previously saved cartridge data, if any. It's loaded into
+   * whatever the mapper has, so a short or missing save is fine. `mapper`
+   * overrides the one named or detected by the header.
+   */
+  pub fn new(rom: Vec<u8>, sram: Option<Vec<u8>>, model: Model, mapper: Option<MapperType>)
+  -> Result<Self, EmuError> {
+    let mut cartridge = Cartridge::parse(&rom)?;
+    if mapper.is_some() { cartridge.mapper = mapper; }
+    let mapper = mapper::new(&cartridge, rom, sram)?;
+    Ok(Self {
+      model,
+      cartridge,
+      mapper,
+      boot_rom: None,
       vram : [0; 0x2000],
-      sram,
       wram : [0; 0x2000],
       oam  : Oam::new(),
       io   : [0; 0x80],
       hram : [0; 0x7F],
       ie   : 0,
-      rom_bank  : 1,
-      sram_bank : 0,
       vram_lock        : false,
       oam_lock         : false,
       dma_transferring : false,
       gamepad : Gamepad::new(),
+      serial  : Serial::new(),
       timer   : Timer::new(),
-      cart_type,
+      t_state : 0,
+      reschedule : true,
+      mapper_synced : 0,
+    })
+  }
+  /**
+   * Maps a boot ROM to run from 0x0000 instead of starting at the cartridge
+   * entry point. DMG boot ROMs cover 0x0000-0x00FF, CGB ones 0x0200-0x08FF as
+   * well, leaving the cartridge header visible in between.
+   */
+  pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), EmuError> {
+    let expected = self.model.boot_rom_size();
+    if boot_rom.len() != expected {
+      return Err(EmuError::BadBootRom { model: self.model, expected, actual: boot_rom.len() });
+    }
+    self.boot_rom = Some(boot_rom);
+    Ok(())
+  }
+  /**
+   * Sets the I/O registers the way the boot ROM leaves them.
+   */
+  pub fn skip_boot(&mut self) {
+    for &(addr, value) in POST_BOOT_IO.iter().chain(self.model.post_boot_io()) {
+      self.io[addr as usize - 0xFF00] = mask(addr, value);
     }
+    self.timer.counter = (self.model.post_boot_div() as u16) << 8;
+    self.timer.tac = self.io[0x07] & 0b_111;
   }
+
   pub fn get(&self, addr: u16) -> u8 {
     let idx = addr as usize;
     match addr {
-      0x0000..=0x3FFF => self.rom [idx - 0x0000],
-      0x4000..=0x7FFF => {
-        self.rom[idx - 0x4000 + self.rom_bank as usize * 0x4000]
-      }
+      0x0000..=0x08FF if self.boot_rom_mapped(addr) =>
+        self.boot_rom.as_ref().map_or(0xFF, |boot_rom| boot_rom[idx]),
+      0x0000..=0x7FFF => self.mapper.read_rom(addr),
       0x8000..=0x9FFF => self.vram[idx - 0x8000],
-      0xA000..=0xBFFF => match &self.sram {
-        Some(sram) => sram[0x2000 * self.sram_bank as usize + idx - 0xA000],
-        None => 0,
-      }
+      0xA000..=0xBFFF => self.mapper.read_ram(addr),
       0xC000..=0xDFFF => self.wram[idx - 0xC000],
       0xE000..=0xFDFF => self.wram[idx - 0xE000],
       0xFE00..=0xFE9F => self.oam.get(addr as u8),
       0xFEA0..=0xFEFF => 0xFF,
       0xFF00 => self.gamepad.get(),
-      0xFF01..=0xFF03 => 0,
-      0xFF04..=0xFF07 => self.timer.get(addr as u8 - 4),
-      0xFF08..=0xFF7F => self.io  [idx - 0xFF00],
+      0xFF01..=0xFF03 => self.serial.get(addr as u8 - 1),
+      0xFF04..=0xFF07 => self.timer.get(addr as u8 - 4, self.t_state),
+      0xFF4D..=0xFF70 if !self.model.has_reg(addr) => 0xFF,
+      0xFF08..=0xFF4F => self.io  [idx - 0xFF00],
+      0xFF50 => 0xFF,
+      0xFF51..=0xFF7F => self.io  [idx - 0xFF00],
       0xFF80..=0xFFFE => self.hram[idx - 0xFF80],
       0xFFFF => self.ie,
     }
@@ -95,43 +138,14 @@ impl Bus {
     value = mask(addr, value);
     let idx = addr as usize;
     match addr {
-      0x0000..=0x7FFF => match self.cart_type {
-        CartType::ROM => {},
-        CartType::MBC1 => match addr {
-          0x0000..=0x1FFF => {},
-          0x2000..=0x3FFF => self.rom_bank = if value & 0x1F == 0 {
-            1
-          } else {
-            value as u16 & 0x1F
-          },
-          0x4000..=0x5FFF => self.sram_bank = value & 0x3,
-          0x6000..=0x7FFF => {},
-          _ => unreachable!(),
-        },
-        CartType::MBC3 => match addr {
-          0x0000..=0x1FFF => {},
-          0x2000..=0x3FFF => self.rom_bank = if value & 0x7F == 0 {
-            1
-          } else {
-            value as u16 & 0x7F
-          },
-          0x4000..=0x5FFF => self.sram_bank = value & 0x3,
-          0x6000..=0x7FFF => {},
-          _ => unreachable!(),
-        },
-        CartType::MBC5 => match addr {
-          0x0000..=0x1FFF => {},
-          0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0xFF00) | value as u16,
-          0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0x00FF) | (value as u16 & 1) << 8,
-          0x4000..=0x5FFF => self.sram_bank = value & 0xF,
-          0x6000..=0x7FFF => {},
-          _ => unreachable!(),
-        },
+      0x0000..=0x7FFF => {
+        self.sync_mapper(self.t_state);
+        self.mapper.write_control(addr, value);
       }
       0x8000..=0x9FFF => if !self.vram_lock { self.vram[idx - 0x8000] = value; }
-      0xA000..=0xBFFF => match &mut self.sram {
-        Some(sram) => sram[0x2000 * self.sram_bank as usize + idx - 0xA000] = value,
-        None => {}
+      0xA000..=0xBFFF => {
+        self.sync_mapper(self.t_state);
+        self.mapper.write_ram(addr, value);
       }
       0xC000..=0xDFFF => self.wram[idx - 0xC000] = value,
       0xE000..=0xFDFF => self.wram[idx - 0xE000] = value,
@@ -144,18 +158,53 @@ impl Bus {
         3 => self.gamepad.region = GamepadRegion::None,
         _ => unreachable!()
       }
-      0xFF01..=0xFF03 => {}
-      0xFF04..=0xFF07 => self.timer.set(addr as u8 - 4, value),
+      0xFF01..=0xFF03 => if self.serial.set(addr as u8 - 1, value) {
+        self.request_interrupt(Interrupt::Serial);
+      }
+      0xFF04..=0xFF07 => {
+        self.timer.set(addr as u8 - 4, value, self.t_state);
+        self.reschedule = true;
+      }
       0xFF08..=0xFF45 => self.io  [idx - 0xFF00] = value,
       0xFF46 => {
         self.io[idx - 0xFF00] = value;
         self.dma((value as u16) << 8);
       }
-      0xFF47..=0xFF7F => self.io  [idx - 0xFF00] = value,
+      0xFF4D..=0xFF70 if !self.model.has_reg(addr) => {}
+      0xFF47..=0xFF4F => self.io  [idx - 0xFF00] = value,
+      0xFF50 => if value != 0 { self.boot_rom = None; }
+      0xFF51..=0xFF7F => self.io  [idx - 0xFF00] = value,
       0xFF80..=0xFFFE => self.hram[idx - 0xFF80] = value,
       0xFFFF => self.ie = value,
     }
   }
+  /**
+   * Ticks the mapper up to `t_state`.
+   */
+  pub fn sync_mapper(&mut self, t_state: u64) {
+    if t_state <= self.mapper_synced { return; }
+    let t_states = (t_state - self.mapper_synced).min(u32::MAX as u64) as u32;
+    self.mapper_synced = t_state;
+    self.mapper.tick(t_states);
+    self.reschedule = true;
+  }
+  /**
+   * T-state at which the mapper next needs ticking, if ever.
+   */
+  pub fn next_mapper_tick(&self) -> Option<u64> {
+    self.mapper.next_tick().map(|t_states| self.mapper_synced + t_states as u64)
+  }
+  pub fn request_interrupt(&mut self, int: Interrupt) {
+    self.io[0x0F] |= 1 << int as u8;
+  }
+  fn boot_rom_mapped(&self, addr: u16) -> bool {
+    let idx = addr as usize;
+    match &self.boot_rom {
+      Some(boot_rom) => idx < boot_rom.len() && !(0x100..0x200).contains(&idx),
+      None => false,
+    }
+  }
+
   pub fn lock_vram(&mut self) { self.vram_lock = true; }
   pub fn unlock_vram(&mut self) { self.vram_lock = false; }
   pub fn lock_oam(&mut self) { self.oam_lock = true; }
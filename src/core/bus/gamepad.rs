@@ -1,7 +1,7 @@
 #[derive(Clone, Copy, Default)]
 pub enum GamepadRegion { #[default] None, DPad, Buttons }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Gamepad {
   pub region : GamepadRegion,
   pub up     : bool,
@@ -23,6 +23,16 @@ impl Gamepad {
         GamepadRegion::Buttons => self.get_buttons(),
     }
   }
+  /**
+   * Whether a held button pulls one of the selected lines low, which wakes the
+   * CPU from STOP.
+   */
+  pub fn line_low(&self) -> bool {
+    match self.region {
+      GamepadRegion::None => false,
+      _ => self.get() & 0b_1111 != 0b_1111,
+    }
+  }
   pub fn get_d_pad(&self) -> u8 {
     0
     | (!self.right as u8) << 0
@@ -0,0 +1,161 @@
+mod camera;
+mod eeprom;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rom;
+mod rtc;
+mod sachen;
+mod tama5;
+mod wisdom_tree;
+
+use crate::core::{cartridge::{Cartridge, MapperType}, error::EmuError};
+
+pub use self::camera::{SENSOR_HEIGHT, SENSOR_SIZE, SENSOR_WIDTH};
+
+use self::{
+  camera::Camera, huc1::HuC1, huc3::HuC3, mbc1::Mbc1, mbc2::Mbc2, mbc3::Mbc3, mbc5::Mbc5,
+  mbc6::Mbc6, mbc7::Mbc7, mmm01::Mmm01, rom::RomOnly, sachen::SachenMmc1, tama5::Tama5,
+  wisdom_tree::WisdomTree,
+};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/**
+ * A cartridge controller. It owns the ROM and any external RAM, and decides
+ * what 0x0000-0x7FFF and 0xA000-0xBFFF map to.
+ */
+pub trait Mapper: MapperClone + Send {
+  /**
+   * `addr` is in 0x0000-0x7FFF.
+   */
+  fn read_rom(&self, addr: u16) -> u8;
+  /**
+   * `addr` is in 0x0000-0x7FFF.
+   */
+  fn write_control(&mut self, addr: u16, value: u8);
+  /**
+   * `addr` is in 0xA000-0xBFFF.
+   */
+  fn read_ram(&self, addr: u16) -> u8;
+  /**
+   * `addr` is in 0xA000-0xBFFF.
+   */
+  fn write_ram(&mut self, addr: u16, value: u8);
+
+  /**
+   * Data that outlives power-off, in the layout of a `.sav` file.
+   */
+  fn save(&self) -> Option<Vec<u8>>;
+  fn load(&mut self, data: &[u8]);
+  /**
+   * Whether data of this size can be given to `load`.
+   */
+  fn accepts_save(&self, len: usize) -> bool {
+    self.save().map_or(0, |data| data.len()) == len
+  }
+
+  /**
+   * Called with the number of T-states elapsed, for clocked hardware like RTC.
+   */
+  fn tick(&mut self, _t_states: u32) {}
+  /**
+   * T-states until `tick` next changes anything, if it ever will. Mappers are
+   * only ticked then and before writes.
+   */
+  fn next_tick(&self) -> Option<u32> { None }
+
+  /**
+   * Whether the cartridge's rumble motor is on.
+   */
+  fn rumble(&self) -> bool { false }
+  /**
+   * Whether the cartridge's infrared LED is on.
+   */
+  fn infrared(&self) -> bool { false }
+  /**
+   * Feeds a cartridge accelerometer, in g. See `Emu::set_tilt`.
+   */
+  fn set_tilt(&mut self, _x: f32, _y: f32) {}
+  /**
+   * Feeds a cartridge image sensor. See `Emu::set_camera_image`.
+   */
+  fn set_camera_image(&mut self, _image: &[u8]) {}
+
+  /**
+   * Bank currently mapped at 0x4000-0x7FFF, for debugging.
+   */
+  fn rom_bank(&self) -> usize;
+  /**
+   * Bank currently mapped at 0xA000-0xBFFF, for debugging.
+   */
+  fn ram_bank(&self) -> usize { 0 }
+}
+
+/**
+ * Lets `Box<dyn Mapper>` be cloned. Implemented for every `Clone` mapper.
+ */
+pub trait MapperClone {
+  fn box_clone(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+  fn box_clone(&self) -> Box<dyn Mapper> { Box::new(self.clone()) }
+}
+
+impl Clone for Box<dyn Mapper> {
+  fn clone(&self) -> Self { self.box_clone() }
+}
+
+pub fn new(cartridge: &Cartridge, rom: Vec<u8>, sram: Option<Vec<u8>>)
+-> Result<Box<dyn Mapper>, EmuError> {
+  let mut mapper: Box<dyn Mapper> = match cartridge.mapper {
+    Some(MapperType::ROM) => Box::new(RomOnly::new(rom, cartridge.ram_size)),
+    Some(MapperType::MBC1) => Box::new(Mbc1::new(rom, cartridge.ram_size)),
+    Some(MapperType::MBC2) => Box::new(Mbc2::new(rom)),
+    Some(MapperType::MMM01) => Box::new(Mmm01::new(rom, cartridge.ram_size)),
+    Some(MapperType::MBC3) => Box::new(Mbc3::new(rom, cartridge.ram_size, cartridge.rtc)),
+    Some(MapperType::MBC5) => Box::new(Mbc5::new(rom, cartridge.ram_size, cartridge.rumble)),
+    Some(MapperType::MBC6) => Box::new(Mbc6::new(rom)),
+    Some(MapperType::MBC7) => Box::new(Mbc7::new(rom)),
+    Some(MapperType::PocketCamera) => Box::new(Camera::new(rom)),
+    Some(MapperType::TAMA5) => Box::new(Tama5::new(rom)),
+    Some(MapperType::HuC3) => Box::new(HuC3::new(rom, cartridge.ram_size)),
+    Some(MapperType::HuC1) => Box::new(HuC1::new(rom, cartridge.ram_size)),
+    Some(MapperType::WisdomTree) => Box::new(WisdomTree::new(rom)),
+    Some(MapperType::SachenMMC1 | MapperType::SachenMMC2) => Box::new(SachenMmc1::new(rom)),
+    _ => return Err(EmuError::UnsupportedMapper(cartridge.cart_type)),
+  };
+  if let Some(sram) = sram.filter(|sram| !sram.is_empty()) {
+    if !mapper.accepts_save(sram.len()) {
+      let expected = mapper.save().map_or(0, |data| data.len());
+      return Err(EmuError::SaveMismatch { expected, actual: sram.len() });
+    }
+    mapper.load(&sram);
+  }
+  Ok(mapper)
+}
+
+fn read_banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
+  data.get(bank * bank_size + offset).copied().unwrap_or(0xFF)
+}
+
+fn write_banked(data: &mut [u8], bank: usize, bank_size: usize, offset: usize, value: u8) {
+  if let Some(byte) = data.get_mut(bank * bank_size + offset) { *byte = value; }
+}
+
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+  let len = ram.len().min(data.len());
+  ram[..len].copy_from_slice(&data[..len]);
+}
+
+fn save_ram(ram: &[u8]) -> Option<Vec<u8>> {
+  if ram.is_empty() { None } else { Some(ram.to_vec()) }
+}
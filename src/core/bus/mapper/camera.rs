@@ -0,0 +1,164 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+pub const SENSOR_SIZE: usize = SENSOR_WIDTH * SENSOR_HEIGHT;
+
+const RAM_SIZE: usize = 0x20000;
+const REGISTERS_SELECT: u8 = 0x10;
+const REG_COUNT: usize = 0x36;
+const REG_CAPTURE: usize = 0x00;
+const REG_EXPOSURE_HI: usize = 0x02;
+const REG_EXPOSURE_LO: usize = 0x03;
+/**
+ * 4x4 matrix of 3 thresholds per pixel, dividing brightness into 4 shades.
+ */
+const REG_DITHER: usize = 0x06;
+
+const CAPTURE_START: u8 = 0b_00000001;
+const CAPTURE_N: u8 = 0b_00000010;
+/**
+ * Where captures land, as 16x14 tiles in bank 0.
+ */
+const IMAGE_ADDR: usize = 0x0100;
+
+/**
+ * Game Boy Camera (Pocket Camera): 1 MiB ROM, 128 KiB RAM and an M64282FP
+ * sensor whose registers replace RAM when bit 4 of the RAM bank is set.
+ *
+ * Captures apply the dithering matrix to the image given by `set_camera_image`.
+ * Gain and edge enhancement are not emulated, and exposure only affects how
+ * long a capture takes.
+ */
+#[derive(Clone)]
+pub struct Camera {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  ram_enabled: bool,
+  rom_bank: u8,
+  ram_bank: u8,
+  registers: [u8; REG_COUNT],
+  image: Vec<u8>,
+  /**
+   * T-states until the capture in progress is done.
+   */
+  capture_t_states: u32,
+}
+
+impl Camera {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self {
+      rom,
+      ram: vec![0; RAM_SIZE],
+      ram_enabled: false,
+      rom_bank: 1,
+      ram_bank: 0,
+      registers: [0; REG_COUNT],
+      image: vec![0x80; SENSOR_SIZE],
+      capture_t_states: 0,
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+  fn ram_bank_n(&self) -> usize { (self.ram_bank & 0xF) as usize }
+  fn registers_mapped(&self) -> bool { self.ram_bank & REGISTERS_SELECT > 0 }
+  fn capturing(&self) -> bool { self.registers[REG_CAPTURE] & CAPTURE_START > 0 }
+
+  /**
+   * Takes 32446 M-cycles, plus 512 without the N bit, plus 16 per exposure
+   * step.
+   */
+  fn start_capture(&mut self) {
+    let exposure = (self.registers[REG_EXPOSURE_HI] as u32) << 8
+      | self.registers[REG_EXPOSURE_LO] as u32;
+    let n = self.registers[REG_CAPTURE] & CAPTURE_N > 0;
+    let m_cycles = 32446 + if n { 0 } else { 512 } + 16 * exposure;
+    self.capture_t_states = m_cycles * 4;
+  }
+
+  fn finish_capture(&mut self) {
+    for y in 0..SENSOR_HEIGHT {
+      for x in 0..SENSOR_WIDTH {
+        let value = self.image[y * SENSOR_WIDTH + x];
+        let thresholds = REG_DITHER + ((y & 3) * 4 + (x & 3)) * 3;
+        let shade = self.registers[thresholds..thresholds + 3]
+          .iter()
+          .filter(|&&threshold| value < threshold)
+          .count() as u8;
+        let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+        let addr = IMAGE_ADDR + tile * 16 + (y % 8) * 2;
+        let bit = 7 - (x % 8);
+        self.ram[addr] = self.ram[addr] & !(1 << bit) | (shade & 1) << bit;
+        self.ram[addr + 1] = self.ram[addr + 1] & !(1 << bit) | (shade >> 1) << bit;
+      }
+    }
+    self.registers[REG_CAPTURE] &= !CAPTURE_START;
+  }
+}
+
+impl Mapper for Camera {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ram_enabled = value & 0xF == 0xA,
+      0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+      0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  /**
+   * Only the capture register reads back. RAM reads as 0 while capturing.
+   */
+  fn read_ram(&self, addr: u16) -> u8 {
+    if self.registers_mapped() {
+      return if addr & 0x7F == 0 { self.registers[REG_CAPTURE] } else { 0x00 };
+    }
+    if self.capturing() { return 0x00; }
+    read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  /**
+   * RAM is readable while disabled, but not writable.
+   */
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if self.registers_mapped() {
+      let reg = (addr & 0x7F) as usize;
+      if reg == REG_CAPTURE {
+        let start = !self.capturing() && value & CAPTURE_START > 0;
+        self.registers[REG_CAPTURE] = value & 0x7;
+        if start { self.start_capture(); }
+      } else if let Some(register) = self.registers.get_mut(reg) {
+        *register = value;
+      }
+      return;
+    }
+    if !self.ram_enabled || self.capturing() { return; }
+    let bank = self.ram_bank_n();
+    write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn tick(&mut self, t_states: u32) {
+    if !self.capturing() { return; }
+    self.capture_t_states = self.capture_t_states.saturating_sub(t_states);
+    if self.capture_t_states == 0 { self.finish_capture(); }
+  }
+  fn next_tick(&self) -> Option<u32> { self.capturing().then_some(self.capture_t_states) }
+
+  fn set_camera_image(&mut self, image: &[u8]) {
+    let len = image.len().min(SENSOR_SIZE);
+    self.image[..len].copy_from_slice(&image[..len]);
+  }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
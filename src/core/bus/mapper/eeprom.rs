@@ -0,0 +1,138 @@
+const WORDS: usize = 128;
+pub const SIZE: usize = WORDS * 2;
+
+const CS: u8 = 0b_10000000;
+const CLK: u8 = 0b_01000000;
+const DI: u8 = 0b_00000010;
+const DO: u8 = 0b_00000001;
+
+/**
+ * Bits shifted in after the start bit: 2 opcode bits and 8 address bits, of
+ * which the top one is ignored in 16-bit organization.
+ */
+const COMMAND_BITS: u8 = 10;
+
+#[derive(Clone, Copy)]
+enum State {
+  Idle,
+  Command { bits: u16, len: u8 },
+  Read { word: u16, len: u8 },
+  Write { addr: Option<u8>, word: u16, len: u8 },
+}
+
+/**
+ * 93LC56 serial EEPROM in 16-bit organization, as found in MBC7 cartridges.
+ * It's bit-banged through a single register: CS in bit 7, CLK in bit 6, DI in
+ * bit 1 and DO in bit 0. Bits are shifted on the rising edge of CLK.
+ */
+#[derive(Clone)]
+pub struct Eeprom {
+  data: [u16; WORDS],
+  pins: u8,
+  data_out: bool,
+  write_enabled: bool,
+  state: State,
+}
+
+impl Eeprom {
+  pub fn new() -> Self {
+    Self {
+      data: [0xFFFF; WORDS],
+      pins: 0,
+      data_out: true,
+      write_enabled: false,
+      state: State::Idle,
+    }
+  }
+
+  pub fn get(&self) -> u8 {
+    self.pins | (self.data_out as u8 * DO)
+  }
+  pub fn set(&mut self, value: u8) {
+    let rising_clk = self.pins & CLK == 0 && value & CLK > 0;
+    self.pins = value & (CS | CLK | DI);
+    if value & CS == 0 {
+      self.state = State::Idle;
+      return;
+    }
+    if rising_clk { self.shift(value & DI > 0); }
+  }
+
+  /**
+   * Words as little-endian pairs of bytes.
+   */
+  pub fn save(&self) -> Vec<u8> {
+    self.data.iter().flat_map(|word| word.to_le_bytes()).collect()
+  }
+  pub fn load(&mut self, data: &[u8]) {
+    for (word, bytes) in self.data.iter_mut().zip(data.chunks_exact(2)) {
+      *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+  }
+
+  fn shift(&mut self, bit: bool) {
+    self.state = match self.state {
+      State::Idle if bit => State::Command { bits: 0, len: 0 },
+      State::Idle => State::Idle,
+      State::Command { bits, len } => {
+        let bits = bits << 1 | bit as u16;
+        if len + 1 < COMMAND_BITS {
+          State::Command { bits, len: len + 1 }
+        } else {
+          self.command(bits)
+        }
+      }
+      State::Read { word, len } => {
+        self.data_out = word & 0x8000 > 0;
+        if len > 1 { State::Read { word: word << 1, len: len - 1 } } else { State::Idle }
+      }
+      State::Write { addr, word, len } => {
+        let word = word << 1 | bit as u16;
+        if len + 1 < 16 {
+          State::Write { addr, word, len: len + 1 }
+        } else {
+          self.write(addr, word)
+        }
+      }
+    }
+  }
+
+  fn command(&mut self, bits: u16) -> State {
+    let addr = (bits & 0x7F) as u8;
+    match (bits >> 8, bits >> 6 & 0b11) {
+      // READ, preceded by a dummy 0 bit
+      (0b10, _) => {
+        self.data_out = false;
+        return State::Read { word: self.data[addr as usize], len: 16 };
+      }
+      // WRITE
+      (0b01, _) => return State::Write { addr: Some(addr), word: 0, len: 0 },
+      // ERASE
+      (0b11, _) => if self.write_enabled { self.data[addr as usize] = 0xFFFF; },
+      // EWDS
+      (0b00, 0b00) => self.write_enabled = false,
+      // WRAL
+      (0b00, 0b01) => return State::Write { addr: None, word: 0, len: 0 },
+      // ERAL
+      (0b00, 0b10) => if self.write_enabled { self.data.fill(0xFFFF); },
+      // EWEN
+      _ => self.write_enabled = true,
+    }
+    self.data_out = true;
+    State::Idle
+  }
+
+  /**
+   * `addr` is `None` for WRAL.
+   */
+  fn write(&mut self, addr: Option<u8>, word: u16) -> State {
+    if self.write_enabled {
+      match addr {
+        Some(addr) => self.data[addr as usize] = word,
+        None => self.data.fill(word),
+      }
+    }
+    self.data_out = true;
+    State::Idle
+  }
+}
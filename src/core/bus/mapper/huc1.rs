@@ -0,0 +1,76 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+/**
+ * Hudson HuC1: MBC1-like banking, with an infrared port in place of RAM enable.
+ */
+#[derive(Clone)]
+pub struct HuC1 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  ir_mode: bool,
+  ir_led: bool,
+  rom_bank: u8,
+  ram_bank: u8,
+}
+
+impl HuC1 {
+  pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      ir_mode: false,
+      ir_led: false,
+      rom_bank: 1,
+      ram_bank: 0,
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+  fn ram_bank_n(&self) -> usize {
+    self.ram_bank as usize % (self.ram.len() / RAM_BANK_SIZE).max(1)
+  }
+}
+
+impl Mapper for HuC1 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ir_mode = value & 0xF == 0xE,
+      0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+      0x4000..=0x5FFF => self.ram_bank = value & 0x3,
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  /**
+   * In IR mode, bit 0 reads 1 when light is received. There's never a link
+   * partner, so it stays dark.
+   */
+  fn read_ram(&self, addr: u16) -> u8 {
+    if self.ir_mode { return 0xC0; }
+    read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if self.ir_mode {
+      self.ir_led = value & 1 > 0;
+      return;
+    }
+    let bank = self.ram_bank_n();
+    write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn infrared(&self) -> bool { self.ir_led }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
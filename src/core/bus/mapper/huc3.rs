@@ -0,0 +1,173 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, write_banked,
+  rtc::MinuteClock};
+
+const MODE_RAM_READ: u8 = 0x0;
+const MODE_RAM: u8 = 0xA;
+const MODE_RTC_COMMAND: u8 = 0xB;
+const MODE_RTC_RESPONSE: u8 = 0xC;
+const MODE_RTC_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8 = 0xE;
+
+/**
+ * Hudson HuC3: RAM, infrared port and an RTC, all behind 0xA000-0xBFFF and
+ * selected by the mode register at 0x0000-0x1FFF.
+ */
+#[derive(Clone)]
+pub struct HuC3 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  clock: MinuteClock,
+  mode: u8,
+  rom_bank: u8,
+  ram_bank: u8,
+  ir_led: bool,
+  /**
+   * RTC memory address for commands. 0x00-0x02 are the minute of the day,
+   * 0x03-0x06 the day counter, 0x58-0x5F the alarm, one nibble each.
+   */
+  access_index: u8,
+  response: u8,
+  alarm_minutes: u16,
+  alarm_days: u16,
+  alarm_enabled: bool,
+}
+
+impl HuC3 {
+  pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      clock: MinuteClock::new(),
+      mode: MODE_RAM_READ,
+      rom_bank: 1,
+      ram_bank: 0,
+      ir_led: false,
+      access_index: 0,
+      response: 0,
+      alarm_minutes: 0,
+      alarm_days: 0,
+      alarm_enabled: false,
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+  fn ram_bank_n(&self) -> usize {
+    self.ram_bank as usize % (self.ram.len() / RAM_BANK_SIZE).max(1)
+  }
+
+  /**
+   * Upper nibble is the command, lower nibble its argument. The response
+   * echoes the command in its upper nibble.
+   */
+  fn rtc_command(&mut self, value: u8) {
+    let arg = value & 0xF;
+    self.response = value;
+    match value >> 4 & 0x7 {
+      0x1 => {
+        self.response = value & 0xF0 | self.get_rtc_nibble(self.access_index);
+        self.access_index = self.access_index.wrapping_add(1);
+      }
+      0x2 => self.set_rtc_nibble(self.access_index, arg),
+      0x3 => {
+        self.set_rtc_nibble(self.access_index, arg);
+        self.access_index = self.access_index.wrapping_add(1);
+      }
+      0x4 => self.access_index = self.access_index & 0xF0 | arg,
+      0x5 => self.access_index = self.access_index & 0x0F | arg << 4,
+      _ => {}
+    }
+  }
+  fn get_rtc_nibble(&self, index: u8) -> u8 {
+    let nibble = |value: u16, i: u8| (value >> (i * 4)) as u8 & 0xF;
+    match index {
+      0x00..=0x02 => nibble(self.clock.minutes, index),
+      0x03..=0x06 => nibble(self.clock.days, index - 0x03),
+      0x58..=0x5A => nibble(self.alarm_minutes, index - 0x58),
+      0x5B..=0x5E => nibble(self.alarm_days, index - 0x5B),
+      0x5F => self.alarm_enabled as u8,
+      _ => 0,
+    }
+  }
+  fn set_rtc_nibble(&mut self, index: u8, value: u8) {
+    let set = |target: &mut u16, i: u8| {
+      *target = *target & !(0xF << (i * 4)) | (value as u16) << (i * 4);
+    };
+    match index {
+      0x00..=0x02 => {
+        set(&mut self.clock.minutes, index);
+        self.clock.minutes %= 1440;
+      }
+      0x03..=0x06 => set(&mut self.clock.days, index - 0x03),
+      0x58..=0x5A => set(&mut self.alarm_minutes, index - 0x58),
+      0x5B..=0x5E => set(&mut self.alarm_days, index - 0x5B),
+      0x5F => self.alarm_enabled = value & 1 > 0,
+      _ => {}
+    }
+  }
+}
+
+impl Mapper for HuC3 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.mode = value & 0xF,
+      0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+      0x4000..=0x5FFF => self.ram_bank = value & 0x3,
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    match self.mode {
+      MODE_RAM_READ | MODE_RAM => {
+        read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+      }
+      MODE_RTC_RESPONSE => self.response,
+      MODE_RTC_SEMAPHORE => 0x01,
+      MODE_IR => 0xC0,
+      _ => 0xFF,
+    }
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    match self.mode {
+      MODE_RAM => {
+        let bank = self.ram_bank_n();
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+      }
+      MODE_RTC_COMMAND => self.rtc_command(value),
+      MODE_IR => self.ir_led = value & 1 > 0,
+      _ => {}
+    }
+  }
+
+  /**
+   * RAM followed by the clock footer.
+   */
+  fn save(&self) -> Option<Vec<u8>> {
+    let mut data = self.ram.clone();
+    self.clock.save(&mut data);
+    Some(data)
+  }
+  fn load(&mut self, data: &[u8]) {
+    load_ram(&mut self.ram, data);
+    if let Some(footer) = data.get(self.ram.len()..) { self.clock.load(footer); }
+  }
+  fn accepts_save(&self, len: usize) -> bool {
+    len == self.ram.len() || len == self.ram.len() + MinuteClock::FOOTER_SIZE
+  }
+
+  fn tick(&mut self, t_states: u32) { self.clock.tick(t_states); }
+  fn next_tick(&self) -> Option<u32> { Some(self.clock.to_next_second()) }
+
+  fn infrared(&self) -> bool { self.ir_led }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
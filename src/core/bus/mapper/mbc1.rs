@@ -0,0 +1,101 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+const LOGO: std::ops::Range<usize> = 0x104..0x134;
+
+#[derive(Clone)]
+pub struct Mbc1 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  ram_enabled: bool,
+  /**
+   * 5-bit register at 0x2000-0x3FFF.
+   */
+  bank1: u8,
+  /**
+   * 2-bit register at 0x4000-0x5FFF, either upper ROM bank bits or RAM bank.
+   */
+  bank2: u8,
+  /**
+   * Mode 1 applies `bank2` to 0x0000-0x3FFF and RAM as well.
+   */
+  mode: bool,
+  /**
+   * MBC1M wires `bank2` to ROM bank bits 4-5 instead of 5-6.
+   */
+  multicart: bool,
+}
+
+impl Mbc1 {
+  pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    let multicart = is_multicart(&rom);
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      ram_enabled: false,
+      bank1: 1,
+      bank2: 0,
+      mode: false,
+      multicart,
+    }
+  }
+
+  fn bank2_shift(&self) -> u8 { if self.multicart { 4 } else { 5 } }
+
+  fn rom_bank_0(&self) -> usize {
+    let bank = if self.mode { (self.bank2 << self.bank2_shift()) as usize } else { 0 };
+    bank % self.rom_banks()
+  }
+  fn rom_bank_n(&self) -> usize {
+    let bank1 = if self.multicart { self.bank1 & 0xF } else { self.bank1 };
+    (bank1 | self.bank2 << self.bank2_shift()) as usize % self.rom_banks()
+  }
+  fn rom_banks(&self) -> usize { (self.rom.len() / ROM_BANK_SIZE).max(1) }
+
+  fn ram_bank_n(&self) -> usize {
+    let ram_banks = (self.ram.len() / RAM_BANK_SIZE).max(1);
+    if self.mode { self.bank2 as usize % ram_banks } else { 0 }
+  }
+}
+
+impl Mapper for Mbc1 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, self.rom_bank_0(), ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ram_enabled = value & 0xF == 0xA,
+      0x2000..=0x3FFF => self.bank1 = if value & 0x1F == 0 { 1 } else { value & 0x1F },
+      0x4000..=0x5FFF => self.bank2 = value & 0x3,
+      0x6000..=0x7FFF => self.mode = value & 1 > 0,
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    let bank = self.ram_bank_n();
+    write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
+
+/**
+ * MBC1M compilations are 1 MiB, and each 256 KiB game starts with its own
+ * header, so the boot logo shows up again at bank 0x10.
+ */
+fn is_multicart(rom: &[u8]) -> bool {
+  const GAME_SIZE: usize = 0x10 * ROM_BANK_SIZE;
+  rom.len() == 0x100000
+    && rom[LOGO] == rom[GAME_SIZE + LOGO.start..GAME_SIZE + LOGO.end]
+}
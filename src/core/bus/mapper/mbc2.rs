@@ -0,0 +1,68 @@
+use super::{Mapper, ROM_BANK_SIZE, load_ram, read_banked, save_ram};
+
+const RAM_SIZE: usize = 512;
+
+/**
+ * Up to 256 KiB ROM and a built-in 512 x 4-bit RAM.
+ */
+#[derive(Clone)]
+pub struct Mbc2 {
+  rom: Vec<u8>,
+  ram: [u8; RAM_SIZE],
+  ram_enabled: bool,
+  rom_bank: u8,
+}
+
+impl Mbc2 {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self { rom, ram: [0; RAM_SIZE], ram_enabled: false, rom_bank: 1 }
+  }
+
+  fn rom_banks(&self) -> usize { (self.rom.len() / ROM_BANK_SIZE).max(1) }
+}
+
+impl Mapper for Mbc2 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => {
+        let bank = self.rom_bank as usize % self.rom_banks();
+        read_banked(&self.rom, bank, ROM_BANK_SIZE, addr as usize - 0x4000)
+      }
+    }
+  }
+  /**
+   * Address bit 8 selects the register: clear for RAM enable, set for ROM bank.
+   */
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x3FFF => if addr & 0x100 == 0 {
+        self.ram_enabled = value & 0xF == 0xA;
+      } else {
+        self.rom_bank = if value & 0xF == 0 { 1 } else { value & 0xF };
+      }
+      0x4000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  /**
+   * Only the lower 9 address bits are decoded, so the RAM echoes across the
+   * whole 0xA000-0xBFFF range. The upper nibble isn't driven and reads as 1s.
+   */
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    self.ram[addr as usize % RAM_SIZE] | 0xF0
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    self.ram[addr as usize % RAM_SIZE] = value & 0x0F;
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) {
+    load_ram(&mut self.ram, data);
+    for byte in self.ram.iter_mut() { *byte &= 0x0F; }
+  }
+
+  fn rom_bank(&self) -> usize { self.rom_bank as usize % self.rom_banks() }
+}
@@ -0,0 +1,115 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, write_banked,
+  rtc::{self, Rtc}};
+
+#[derive(Clone)]
+pub struct Mbc3 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  rtc: Option<Rtc>,
+  ram_enabled: bool,
+  rom_bank: u8,
+  /**
+   * 0x00-0x03 selects a RAM bank, 0x08-0x0C an RTC register.
+   */
+  ram_bank: u8,
+  latch_value: u8,
+}
+
+impl Mbc3 {
+  pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      rtc: if has_rtc { Some(Rtc::new()) } else { None },
+      ram_enabled: false,
+      rom_bank: 1,
+      ram_bank: 0,
+      latch_value: 0xFF,
+    }
+  }
+
+  fn rom_banks(&self) -> usize { (self.rom.len() / ROM_BANK_SIZE).max(1) }
+  fn ram_banks(&self) -> usize { (self.ram.len() / RAM_BANK_SIZE).max(1) }
+}
+
+impl Mapper for Mbc3 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => {
+        let bank = self.rom_bank as usize % self.rom_banks();
+        read_banked(&self.rom, bank, ROM_BANK_SIZE, addr as usize - 0x4000)
+      }
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ram_enabled = value & 0xF == 0xA,
+      0x2000..=0x3FFF => self.rom_bank = if value & 0x7F == 0 { 1 } else { value & 0x7F },
+      0x4000..=0x5FFF => self.ram_bank = value & 0xF,
+      0x6000..=0x7FFF => {
+        if self.latch_value == 0 && value == 1 {
+          if let Some(rtc) = &mut self.rtc { rtc.latch(); }
+        }
+        self.latch_value = value;
+      }
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    match (self.ram_bank, &self.rtc) {
+      (0x00..=0x03, _) => {
+        let bank = self.ram_bank as usize % self.ram_banks();
+        read_banked(&self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000)
+      }
+      (0x08..=0x0C, Some(rtc)) => rtc.get(self.ram_bank),
+      _ => 0xFF,
+    }
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    match self.ram_bank {
+      0x00..=0x03 => {
+        let bank = self.ram_bank as usize % self.ram_banks();
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+      }
+      0x08..=0x0C => if let Some(rtc) = &mut self.rtc { rtc.set(self.ram_bank, value); }
+      _ => {}
+    }
+  }
+
+  /**
+   * RAM followed by the RTC footer, if the cartridge has a clock.
+   */
+  fn save(&self) -> Option<Vec<u8>> {
+    if self.ram.is_empty() && self.rtc.is_none() { return None; }
+    let mut data = self.ram.clone();
+    if let Some(rtc) = &self.rtc { rtc.save(&mut data); }
+    Some(data)
+  }
+  /**
+   * Saves without the RTC footer or with the older 44-byte one are fine too.
+   */
+  fn accepts_save(&self, len: usize) -> bool {
+    let footer_sizes: &[usize] = match self.rtc {
+      Some(_) => &[0, rtc::FOOTER_SIZE_32, rtc::FOOTER_SIZE],
+      None => &[0],
+    };
+    footer_sizes.iter().any(|footer_size| self.ram.len() + footer_size == len)
+  }
+  fn load(&mut self, data: &[u8]) {
+    load_ram(&mut self.ram, data);
+    if let (Some(rtc), Some(footer)) = (&mut self.rtc, data.get(self.ram.len()..)) {
+      rtc.load(footer);
+    }
+  }
+
+  fn tick(&mut self, t_states: u32) {
+    if let Some(rtc) = &mut self.rtc { rtc.tick(t_states); }
+  }
+  fn next_tick(&self) -> Option<u32> { self.rtc.as_ref()?.to_next_second() }
+
+  fn rom_bank(&self) -> usize { self.rom_bank as usize % self.rom_banks() }
+  fn ram_bank(&self) -> usize { self.ram_bank as usize }
+}
@@ -0,0 +1,79 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+const RUMBLE_MOTOR: u8 = 0b_1000;
+
+#[derive(Clone)]
+pub struct Mbc5 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  ram_enabled: bool,
+  rom_bank: u16,
+  ram_bank: u8,
+  /**
+   * On rumble carts, bit 3 of the RAM bank register drives the motor instead.
+   */
+  has_rumble: bool,
+  rumble: bool,
+}
+
+impl Mbc5 {
+  pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      ram_enabled: false,
+      rom_bank: 1,
+      ram_bank: 0,
+      has_rumble,
+      rumble: false,
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+  fn ram_bank_n(&self) -> usize {
+    self.ram_bank as usize % (self.ram.len() / RAM_BANK_SIZE).max(1)
+  }
+}
+
+impl Mapper for Mbc5 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+      0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0xFF00) | value as u16,
+      0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0x00FF) | (value as u16 & 1) << 8,
+      0x4000..=0x5FFF => if self.has_rumble {
+        self.rumble = value & RUMBLE_MOTOR > 0;
+        self.ram_bank = value & 0x7;
+      } else {
+        self.ram_bank = value & 0xF;
+      }
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    let bank = self.ram_bank_n();
+    write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn rumble(&self) -> bool { self.rumble }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
@@ -0,0 +1,186 @@
+use super::{Mapper, load_ram, read_banked, write_banked};
+
+/**
+ * ROM, flash and RAM are all switched in halves of their usual window.
+ */
+const ROM_HALF_BANK_SIZE: usize = 0x2000;
+const RAM_HALF_BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 0x8000;
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x10000;
+
+const FLASH_MANUFACTURER_ID: u8 = 0xC2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+/**
+ * Progress through the JEDEC command sequence: 0xAA to 0x5555, 0x55 to 0x2AAA,
+ * then the command to 0x5555. Erase commands repeat the unlock sequence.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+  Ready,
+  Unlock1,
+  Unlock2,
+  Program,
+  Erase,
+  EraseUnlock1,
+  EraseUnlock2,
+  Id,
+}
+
+/**
+ * MBC6, with 1 MiB of Macronix flash besides ROM and 32 KiB of RAM. Each half
+ * of 0x4000-0x7FFF maps an 8 KiB ROM or flash bank, and each half of
+ * 0xA000-0xBFFF a 4 KiB RAM bank.
+ *
+ * Flash sectors are treated as uniform 64 KiB blocks, and commands complete
+ * instantly.
+ */
+#[derive(Clone)]
+pub struct Mbc6 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  flash: Vec<u8>,
+  ram_enabled: bool,
+  ram_banks: [u8; 2],
+  rom_banks: [u8; 2],
+  /**
+   * Whether each half of 0x4000-0x7FFF maps flash rather than ROM.
+   */
+  flash_mapped: [bool; 2],
+  flash_enabled: bool,
+  flash_write_enabled: bool,
+  flash_state: FlashState,
+}
+
+impl Mbc6 {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self {
+      rom,
+      ram: vec![0; RAM_SIZE],
+      flash: vec![0xFF; FLASH_SIZE],
+      ram_enabled: false,
+      ram_banks: [0, 0],
+      rom_banks: [0, 0],
+      flash_mapped: [false, false],
+      flash_enabled: false,
+      flash_write_enabled: false,
+      flash_state: FlashState::Ready,
+    }
+  }
+
+  /**
+   * Index of the half, and the offset within it.
+   */
+  fn half(addr: u16, half_size: usize) -> (usize, usize) {
+    let offset = addr as usize & (half_size * 2 - 1);
+    (offset / half_size, offset % half_size)
+  }
+
+  fn flash_addr(&self, half: usize, offset: usize) -> usize {
+    (self.rom_banks[half] as usize * ROM_HALF_BANK_SIZE + offset) % FLASH_SIZE
+  }
+
+  fn write_flash(&mut self, addr: usize, value: u8) {
+    use FlashState::*;
+    let command_addr = addr & 0x7FFF;
+    self.flash_state = match (self.flash_state, command_addr, value) {
+      (_, _, 0xF0) => Ready,
+      (Ready | Id, 0x5555, 0xAA) => Unlock1,
+      (Unlock1, 0x2AAA, 0x55) => Unlock2,
+      (Unlock2, 0x5555, 0xA0) => Program,
+      (Unlock2, 0x5555, 0x80) => Erase,
+      (Unlock2, 0x5555, 0x90) => Id,
+      (Program, _, _) => {
+        // Programming can only clear bits
+        if self.flash_write_enabled { self.flash[addr] &= value; }
+        Ready
+      }
+      (Erase, 0x5555, 0xAA) => EraseUnlock1,
+      (EraseUnlock1, 0x2AAA, 0x55) => EraseUnlock2,
+      (EraseUnlock2, 0x5555, 0x10) => {
+        if self.flash_write_enabled { self.flash.fill(0xFF); }
+        Ready
+      }
+      (EraseUnlock2, _, 0x30) => {
+        if self.flash_write_enabled {
+          let sector = addr / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+          self.flash[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+        }
+        Ready
+      }
+      (Id, _, _) => Id,
+      _ => Ready,
+    }
+  }
+}
+
+impl Mapper for Mbc6 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    if addr < 0x4000 { return read_banked(&self.rom, 0, ROM_HALF_BANK_SIZE, addr as usize); }
+    let (half, offset) = Self::half(addr, ROM_HALF_BANK_SIZE);
+    if !self.flash_mapped[half] {
+      let bank = self.rom_banks[half] as usize % (self.rom.len() / ROM_HALF_BANK_SIZE).max(1);
+      return read_banked(&self.rom, bank, ROM_HALF_BANK_SIZE, offset);
+    }
+    if !self.flash_enabled { return 0xFF; }
+    match (self.flash_state, offset) {
+      (FlashState::Id, 0) => FLASH_MANUFACTURER_ID,
+      (FlashState::Id, 1) => FLASH_DEVICE_ID,
+      _ => self.flash[self.flash_addr(half, offset)],
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x03FF => self.ram_enabled = value & 0xF == 0xA,
+      0x0400..=0x07FF => self.ram_banks[0] = value & 0x7,
+      0x0800..=0x0BFF => self.ram_banks[1] = value & 0x7,
+      0x0C00..=0x0FFF => self.flash_enabled = value & 1 > 0,
+      0x1000..=0x1FFF => self.flash_write_enabled = value & 1 > 0,
+      0x2000..=0x27FF => self.rom_banks[0] = value & 0x7F,
+      0x2800..=0x2FFF => self.flash_mapped[0] = value == 0x08,
+      0x3000..=0x37FF => self.rom_banks[1] = value & 0x7F,
+      0x3800..=0x3FFF => self.flash_mapped[1] = value == 0x08,
+      _ => {
+        let (half, offset) = Self::half(addr, ROM_HALF_BANK_SIZE);
+        if self.flash_mapped[half] && self.flash_enabled {
+          let flash_addr = self.flash_addr(half, offset);
+          self.write_flash(flash_addr, value);
+        }
+      }
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    let (half, offset) = Self::half(addr, RAM_HALF_BANK_SIZE);
+    read_banked(&self.ram, self.ram_banks[half] as usize, RAM_HALF_BANK_SIZE, offset)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    let (half, offset) = Self::half(addr, RAM_HALF_BANK_SIZE);
+    let bank = self.ram_banks[half] as usize;
+    write_banked(&mut self.ram, bank, RAM_HALF_BANK_SIZE, offset, value);
+  }
+
+  /**
+   * RAM followed by the flash.
+   */
+  fn save(&self) -> Option<Vec<u8>> {
+    let mut data = self.ram.clone();
+    data.extend_from_slice(&self.flash);
+    Some(data)
+  }
+  fn load(&mut self, data: &[u8]) {
+    load_ram(&mut self.ram, data);
+    if let Some(flash) = data.get(RAM_SIZE..) { load_ram(&mut self.flash, flash); }
+  }
+  /**
+   * Saves of RAM alone are fine too.
+   */
+  fn accepts_save(&self, len: usize) -> bool {
+    len == RAM_SIZE || len == RAM_SIZE + FLASH_SIZE
+  }
+
+  fn rom_bank(&self) -> usize { self.rom_banks[0] as usize }
+  fn ram_bank(&self) -> usize { self.ram_banks[0] as usize }
+}
@@ -0,0 +1,99 @@
+use super::{Mapper, ROM_BANK_SIZE, read_banked, eeprom::{self, Eeprom}};
+
+/**
+ * Accelerometer reading when level, and its change per g.
+ */
+const TILT_CENTER: f32 = 0x81D0 as f32;
+const TILT_PER_G: f32 = 0x70 as f32;
+const TILT_ERASED: u16 = 0x8000;
+
+/**
+ * MBC7, with a 2-axis accelerometer and a 93LC56 EEPROM instead of RAM. Its
+ * registers are mirrored through 0xA000-0xAFFF and selected by address bits
+ * 4-7.
+ */
+#[derive(Clone)]
+pub struct Mbc7 {
+  rom: Vec<u8>,
+  eeprom: Eeprom,
+  /**
+   * Enabling needs 0x0A at 0x0000-0x1FFF and 0x40 at 0x4000-0x5FFF.
+   */
+  ram_enabled: (bool, bool),
+  rom_bank: u8,
+  tilt: (f32, f32),
+  latched: (u16, u16),
+  latch_erased: bool,
+}
+
+impl Mbc7 {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self {
+      rom,
+      eeprom: Eeprom::new(),
+      ram_enabled: (false, false),
+      rom_bank: 1,
+      tilt: (0.0, 0.0),
+      latched: (TILT_ERASED, TILT_ERASED),
+      latch_erased: false,
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+}
+
+impl Mapper for Mbc7 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.ram_enabled.0 = value == 0x0A,
+      0x2000..=0x3FFF => self.rom_bank = value,
+      0x4000..=0x5FFF => self.ram_enabled.1 = value == 0x40,
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if self.ram_enabled != (true, true) || addr >= 0xB000 { return 0xFF; }
+    match addr >> 4 & 0xF {
+      0x2 => self.latched.0 as u8,
+      0x3 => (self.latched.0 >> 8) as u8,
+      0x4 => self.latched.1 as u8,
+      0x5 => (self.latched.1 >> 8) as u8,
+      0x6 => 0x00,
+      0x8 => self.eeprom.get(),
+      _ => 0xFF,
+    }
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if self.ram_enabled != (true, true) || addr >= 0xB000 { return; }
+    match (addr >> 4 & 0xF, value) {
+      (0x0, 0x55) => {
+        self.latched = (TILT_ERASED, TILT_ERASED);
+        self.latch_erased = true;
+      }
+      (0x1, 0xAA) if self.latch_erased => {
+        let axis = |g: f32| (TILT_CENTER + g * TILT_PER_G) as u16;
+        self.latched = (axis(self.tilt.0), axis(self.tilt.1));
+        self.latch_erased = false;
+      }
+      (0x8, _) => self.eeprom.set(value),
+      _ => {}
+    }
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { Some(self.eeprom.save()) }
+  fn load(&mut self, data: &[u8]) { self.eeprom.load(data); }
+  fn accepts_save(&self, len: usize) -> bool { len == eeprom::SIZE }
+
+  fn set_tilt(&mut self, x: f32, y: f32) { self.tilt = (x, y); }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+}
@@ -0,0 +1,120 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+const MAP_LOCK: u8 = 0b_01000000;
+
+/**
+ * MMM01 multicart controller. It boots into the menu in the last 32 KiB of ROM
+ * with every register writable. Once the menu sets the lock bit, the selected
+ * game's base bank and size are fixed, and it sees an MBC1-like controller
+ * confined to its own slice of ROM and RAM.
+ */
+#[derive(Clone)]
+pub struct Mmm01 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  locked: bool,
+  ram_enabled: bool,
+  /**
+   * ROM bank bits 0-8: 0-4 from 0x2000-0x3FFF, 5-6 from its bits 5-6 and 7-8
+   * from bits 4-5 of 0x4000-0x5FFF, the latter two only until locked.
+   */
+  rom_bank: u16,
+  /**
+   * Bits 1-4 of `rom_bank` that stay fixed after locking, set by bits 2-5 of
+   * 0x6000-0x7FFF.
+   */
+  rom_mask: u16,
+  /**
+   * RAM bank bits 0-3: 0-1 from 0x4000-0x5FFF, and 2-3 from its bits 2-3
+   * until locked.
+   */
+  ram_bank: u8,
+  mode: bool,
+}
+
+impl Mmm01 {
+  pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    Self {
+      rom,
+      ram: vec![0; ram_size],
+      locked: false,
+      ram_enabled: false,
+      rom_bank: 0,
+      rom_mask: 0,
+      ram_bank: 0,
+      mode: false,
+    }
+  }
+
+  fn rom_banks(&self) -> usize { (self.rom.len() / ROM_BANK_SIZE).max(1) }
+
+  /**
+   * Bits the game can't switch: the base of its slice.
+   */
+  fn game_base(&self) -> u16 { self.rom_bank & !(0x1F & !self.rom_mask) }
+
+  fn rom_bank_0(&self) -> usize {
+    if !self.locked { return self.rom_banks() - 2; }
+    self.game_base() as usize % self.rom_banks()
+  }
+  fn rom_bank_n(&self) -> usize {
+    if !self.locked { return self.rom_banks() - 1; }
+    let switchable = 0x1F & !self.rom_mask;
+    let bank = self.rom_bank & switchable;
+    let bank = if bank == 0 { 1 & switchable } else { bank };
+    (self.game_base() | bank) as usize % self.rom_banks()
+  }
+  fn ram_bank_n(&self) -> usize {
+    let ram_banks = (self.ram.len() / RAM_BANK_SIZE).max(1);
+    let bank = if self.locked && !self.mode { self.ram_bank & !0x3 } else { self.ram_bank };
+    bank as usize % ram_banks
+  }
+}
+
+impl Mapper for Mmm01 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, self.rom_bank_0(), ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match (addr, self.locked) {
+      (0x0000..=0x1FFF, _) => {
+        self.ram_enabled = value & 0xF == 0xA;
+        if value & MAP_LOCK > 0 { self.locked = true; }
+      }
+      (0x2000..=0x3FFF, false) => self.rom_bank = self.rom_bank & !0x7F | value as u16 & 0x7F,
+      (0x2000..=0x3FFF, true) => {
+        let switchable = 0x1F & !self.rom_mask;
+        self.rom_bank = self.rom_bank & !switchable | value as u16 & switchable;
+      }
+      (0x4000..=0x5FFF, false) => {
+        self.ram_bank = value & 0xF;
+        self.rom_bank = self.rom_bank & 0x7F | ((value as u16 >> 4) & 0x3) << 7;
+      }
+      (0x4000..=0x5FFF, true) => self.ram_bank = self.ram_bank & !0x3 | value & 0x3,
+      (0x6000..=0x7FFF, false) => {
+        self.mode = value & 1 > 0;
+        self.rom_mask = ((value as u16 >> 2) & 0xF) << 1;
+      }
+      (0x6000..=0x7FFF, true) => self.mode = value & 1 > 0,
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, addr: u16) -> u8 {
+    if !self.ram_enabled { return 0xFF; }
+    read_banked(&self.ram, self.ram_bank_n(), RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if !self.ram_enabled { return; }
+    let bank = self.ram_bank_n();
+    write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+  fn ram_bank(&self) -> usize { self.ram_bank_n() }
+}
@@ -0,0 +1,34 @@
+use super::{Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE, load_ram, read_banked, save_ram, write_banked};
+
+/**
+ * No controller, optionally with up to 8 KiB RAM.
+ */
+#[derive(Clone)]
+pub struct RomOnly {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+}
+
+impl RomOnly {
+  pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    Self { rom, ram: vec![0; ram_size.min(RAM_BANK_SIZE)] }
+  }
+}
+
+impl Mapper for RomOnly {
+  fn read_rom(&self, addr: u16) -> u8 {
+    read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize)
+  }
+  fn write_control(&mut self, _addr: u16, _value: u8) {}
+  fn read_ram(&self, addr: u16) -> u8 {
+    read_banked(&self.ram, 0, RAM_BANK_SIZE, addr as usize - 0xA000)
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    write_banked(&mut self.ram, 0, RAM_BANK_SIZE, addr as usize - 0xA000, value);
+  }
+
+  fn save(&self) -> Option<Vec<u8>> { save_ram(&self.ram) }
+  fn load(&mut self, data: &[u8]) { load_ram(&mut self.ram, data); }
+
+  fn rom_bank(&self) -> usize { 1 }
+}
@@ -0,0 +1,226 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const T_STATES_PER_SECOND: u32 = 4194304;
+/**
+ * Size of the RTC footer appended to `.sav` files by VBA-M, BGB, mGBA etc.:
+ * 5 live registers and 5 latched ones as little-endian u32, then a u64 UNIX
+ * timestamp.
+ */
+pub const FOOTER_SIZE: usize = 48;
+/**
+ * Same, with a u32 timestamp.
+ */
+pub const FOOTER_SIZE_32: usize = 44;
+
+const DH_DAY_BIT_8: u8 = 0b_00000001;
+const DH_HALT: u8 = 0b_01000000;
+const DH_CARRY: u8 = 0b_10000000;
+
+/**
+ * MBC3 real-time clock. Registers are 0x08-0x0C: seconds, minutes, hours,
+ * day counter low byte, and day bit 8 / halt / day carry.
+ */
+#[derive(Clone, Default)]
+pub struct Rtc {
+  seconds: u8,
+  minutes: u8,
+  hours: u8,
+  days: u16,
+  halt: bool,
+  carry: bool,
+  latched: [u8; 5],
+  sub_second: u32,
+}
+
+impl Rtc {
+  pub fn new() -> Self { Self::default() }
+
+  pub fn tick(&mut self, t_states: u32) {
+    if self.halt { return; }
+    let t_states = self.sub_second as u64 + t_states as u64;
+    for _ in 0..t_states / T_STATES_PER_SECOND as u64 {
+      self.tick_second();
+    }
+    self.sub_second = (t_states % T_STATES_PER_SECOND as u64) as u32;
+  }
+  /**
+   * `None` while halted.
+   */
+  pub fn to_next_second(&self) -> Option<u32> {
+    (!self.halt).then_some(T_STATES_PER_SECOND - self.sub_second)
+  }
+
+  pub fn latch(&mut self) {
+    for reg in 0x08..=0x0C {
+      self.latched[reg as usize - 0x08] = self.get_live(reg);
+    }
+  }
+
+  /**
+   * Reads a latched register.
+   */
+  pub fn get(&self, reg: u8) -> u8 {
+    match reg {
+      0x08..=0x0C => self.latched[reg as usize - 0x08],
+      _ => 0xFF,
+    }
+  }
+  /**
+   * Writes a live register. Unused bits are dropped, but out-of-range values
+   * are kept and counted like the real chip does.
+   */
+  pub fn set(&mut self, reg: u8, value: u8) {
+    match reg {
+      0x08 => {
+        self.seconds = value & 0x3F;
+        self.sub_second = 0;
+      }
+      0x09 => self.minutes = value & 0x3F,
+      0x0A => self.hours = value & 0x1F,
+      0x0B => self.days = self.days & 0x100 | value as u16,
+      0x0C => {
+        self.days = self.days & 0xFF | ((value & DH_DAY_BIT_8) as u16) << 8;
+        self.halt = value & DH_HALT > 0;
+        self.carry = value & DH_CARRY > 0;
+      }
+      _ => {}
+    }
+  }
+
+  pub fn save(&self, data: &mut Vec<u8>) {
+    for reg in 0x08..=0x0C {
+      data.extend_from_slice(&(self.get_live(reg) as u32).to_le_bytes());
+    }
+    for &value in self.latched.iter() {
+      data.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    data.extend_from_slice(&unix_time().to_le_bytes());
+  }
+  /**
+   * Restores the registers and catches up with the host time elapsed since
+   * the footer was written.
+   */
+  pub fn load(&mut self, footer: &[u8]) {
+    if footer.len() < FOOTER_SIZE_32 { return; }
+    let word = |i: usize| footer[i * 4];
+    for reg in 0x08..=0x0C {
+      self.set(reg, word(reg as usize - 0x08));
+    }
+    for i in 0..5 {
+      self.latched[i] = word(5 + i);
+    }
+    let mut timestamp = [0; 8];
+    let timestamp_len = footer.len().min(FOOTER_SIZE) - 40;
+    timestamp[..timestamp_len].copy_from_slice(&footer[40..40 + timestamp_len]);
+    let elapsed = unix_time().saturating_sub(u64::from_le_bytes(timestamp));
+    if !self.halt { self.advance(elapsed); }
+  }
+
+  fn get_live(&self, reg: u8) -> u8 {
+    match reg {
+      0x08 => self.seconds,
+      0x09 => self.minutes,
+      0x0A => self.hours,
+      0x0B => self.days as u8,
+      0x0C => (self.days >> 8) as u8 & DH_DAY_BIT_8
+        | if self.halt { DH_HALT } else { 0 }
+        | if self.carry { DH_CARRY } else { 0 },
+      _ => 0xFF,
+    }
+  }
+
+  /**
+   * Each counter wraps to 0 with a carry when it reaches its limit, but one
+   * that was set past the limit only wraps at its bit width, without carry.
+   */
+  fn tick_second(&mut self) {
+    let (seconds, carry) = count(self.seconds, 60, 0x3F);
+    self.seconds = seconds;
+    if !carry { return; }
+    let (minutes, carry) = count(self.minutes, 60, 0x3F);
+    self.minutes = minutes;
+    if !carry { return; }
+    let (hours, carry) = count(self.hours, 24, 0x1F);
+    self.hours = hours;
+    if !carry { return; }
+    self.days += 1;
+    if self.days >= 0x200 {
+      self.days = 0;
+      self.carry = true;
+    }
+  }
+
+  fn advance(&mut self, mut seconds: u64) {
+    while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+      self.tick_second();
+      seconds -= 1;
+    }
+    let total = seconds
+      + self.seconds as u64
+      + self.minutes as u64 * 60
+      + self.hours as u64 * 3600
+      + self.days as u64 * 86400;
+    self.seconds = (total % 60) as u8;
+    self.minutes = (total / 60 % 60) as u8;
+    self.hours = (total / 3600 % 24) as u8;
+    let days = total / 86400;
+    if days >= 0x200 { self.carry = true; }
+    self.days = (days % 0x200) as u16;
+  }
+}
+
+fn count(value: u8, limit: u8, mask: u8) -> (u8, bool) {
+  if value + 1 == limit { (0, true) } else { ((value + 1) & mask, false) }
+}
+
+fn unix_time() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/**
+ * Minute-resolution clock of HuC3 and TAMA5 (TAMA6 chip): minute of the day
+ * and a day counter. Its save footer is the u64 UNIX timestamp, then minutes
+ * and days as little-endian u16.
+ */
+#[derive(Clone, Default)]
+pub struct MinuteClock {
+  pub minutes: u16,
+  pub days: u16,
+  seconds: u8,
+  sub_second: u32,
+}
+
+impl MinuteClock {
+  pub const FOOTER_SIZE: usize = 12;
+
+  pub fn new() -> Self { Self::default() }
+
+  pub fn tick(&mut self, t_states: u32) {
+    let t_states = self.sub_second as u64 + t_states as u64;
+    self.advance(t_states / T_STATES_PER_SECOND as u64);
+    self.sub_second = (t_states % T_STATES_PER_SECOND as u64) as u32;
+  }
+  pub fn to_next_second(&self) -> u32 { T_STATES_PER_SECOND - self.sub_second }
+
+  pub fn save(&self, data: &mut Vec<u8>) {
+    data.extend_from_slice(&unix_time().to_le_bytes());
+    data.extend_from_slice(&self.minutes.to_le_bytes());
+    data.extend_from_slice(&self.days.to_le_bytes());
+  }
+  pub fn load(&mut self, footer: &[u8]) {
+    if footer.len() < Self::FOOTER_SIZE { return; }
+    let mut timestamp = [0; 8];
+    timestamp.copy_from_slice(&footer[0..8]);
+    self.minutes = u16::from_le_bytes([footer[8], footer[9]]) % 1440;
+    self.days = u16::from_le_bytes([footer[10], footer[11]]);
+    self.advance(unix_time().saturating_sub(u64::from_le_bytes(timestamp)));
+  }
+
+  fn advance(&mut self, seconds: u64) {
+    let total = self.seconds as u64 + seconds;
+    self.seconds = (total % 60) as u8;
+    let minutes = self.minutes as u64 + total / 60;
+    self.minutes = (minutes % 1440) as u16;
+    self.days = self.days.wrapping_add((minutes / 1440) as u16);
+  }
+}
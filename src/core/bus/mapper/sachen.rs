@@ -0,0 +1,61 @@
+use super::{Mapper, ROM_BANK_SIZE, read_banked};
+
+/**
+ * Sachen MMC1, and MMC2 which banks the same way. Compilations are split by a
+ * base bank and a mask: the masked bits of the ROM bank come from the base,
+ * the rest from the bank register. Base and mask are only writable while bits
+ * 4-5 of the ROM bank are set.
+ *
+ * The cart starts unlocked, as it would be after the boot ROM. The address
+ * scrambling they use to pass the logo check, and MMC2's extra lock state for
+ * the CGB boot ROM, aren't emulated.
+ */
+#[derive(Clone)]
+pub struct SachenMmc1 {
+  rom: Vec<u8>,
+  base_bank: u8,
+  rom_bank: u8,
+  mask: u8,
+}
+
+impl SachenMmc1 {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self { rom, base_bank: 0, rom_bank: 1, mask: 0 }
+  }
+
+  fn rom_banks(&self) -> usize { (self.rom.len() / ROM_BANK_SIZE).max(1) }
+
+  fn rom_bank_0(&self) -> usize {
+    (self.base_bank & self.mask) as usize % self.rom_banks()
+  }
+  fn rom_bank_n(&self) -> usize {
+    let bank = self.base_bank & self.mask | self.rom_bank & !self.mask;
+    bank as usize % self.rom_banks()
+  }
+  fn registers_unlocked(&self) -> bool { self.rom_bank & 0x30 == 0x30 }
+}
+
+impl Mapper for SachenMmc1 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, self.rom_bank_0(), ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => if self.registers_unlocked() { self.base_bank = value; },
+      0x2000..=0x3FFF => self.rom_bank = if value == 0 { 1 } else { value },
+      0x4000..=0x5FFF => if self.registers_unlocked() { self.mask = value; },
+      0x6000..=0x7FFF => {},
+      _ => unreachable!(),
+    }
+  }
+  fn read_ram(&self, _addr: u16) -> u8 { 0xFF }
+  fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+  fn save(&self) -> Option<Vec<u8>> { None }
+  fn load(&mut self, _data: &[u8]) {}
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+}
@@ -0,0 +1,144 @@
+use super::{Mapper, ROM_BANK_SIZE, load_ram, read_banked, rtc::MinuteClock};
+
+const RAM_SIZE: usize = 32;
+
+const REG_BANK_LO: u8 = 0x0;
+const REG_BANK_HI: u8 = 0x1;
+const REG_WRITE_LO: u8 = 0x4;
+const REG_WRITE_HI: u8 = 0x5;
+/**
+ * Bit 0 is address bit 4, bits 1-3 the command.
+ */
+const REG_ADDR_HI: u8 = 0x6;
+/**
+ * Writing it runs the command.
+ */
+const REG_ADDR_LO: u8 = 0x7;
+const REG_ACTIVE: u8 = 0xA;
+const REG_READ_LO: u8 = 0xC;
+const REG_READ_HI: u8 = 0xD;
+
+const CMD_RAM_WRITE: u8 = 0x0;
+const CMD_RAM_READ: u8 = 0x1;
+const CMD_RTC: u8 = 0x2;
+
+const RTC_MINUTE_WRITE: u8 = 0x4;
+const RTC_HOUR_WRITE: u8 = 0x5;
+const RTC_MINUTE_READ: u8 = 0x6;
+const RTC_HOUR_READ: u8 = 0x7;
+
+/**
+ * Bandai TAMA5, with 32 bytes of battery RAM and a TAMA6 clock. Everything is
+ * accessed a nibble at a time: 0xA001 selects a register, 0xA000 reads or
+ * writes it.
+ */
+#[derive(Clone)]
+pub struct Tama5 {
+  rom: Vec<u8>,
+  ram: [u8; RAM_SIZE],
+  clock: MinuteClock,
+  reg: u8,
+  registers: [u8; 8],
+}
+
+impl Tama5 {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self {
+      rom,
+      ram: [0; RAM_SIZE],
+      clock: MinuteClock::new(),
+      reg: 0,
+      registers: [0; 8],
+    }
+  }
+
+  fn rom_bank_n(&self) -> usize {
+    let bank = self.registers[REG_BANK_LO as usize] | self.registers[REG_BANK_HI as usize] << 4;
+    bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+  }
+  fn command(&self) -> u8 { self.registers[REG_ADDR_HI as usize] >> 1 }
+  fn address(&self) -> u8 {
+    (self.registers[REG_ADDR_HI as usize] & 1) << 4 | self.registers[REG_ADDR_LO as usize]
+  }
+  fn data(&self) -> u8 {
+    self.registers[REG_WRITE_HI as usize] << 4 | self.registers[REG_WRITE_LO as usize]
+  }
+
+  fn run_command(&mut self) {
+    let address = self.address();
+    let data = self.data();
+    match (self.command(), address) {
+      (CMD_RAM_WRITE, _) => self.ram[address as usize] = data,
+      (CMD_RTC, RTC_MINUTE_WRITE) => {
+        let hours = self.clock.minutes / 60;
+        self.clock.minutes = hours * 60 + from_bcd(data).min(59);
+      }
+      (CMD_RTC, RTC_HOUR_WRITE) => {
+        let minutes = self.clock.minutes % 60;
+        self.clock.minutes = from_bcd(data).min(23) * 60 + minutes;
+      }
+      _ => {}
+    }
+  }
+  fn result(&self) -> u8 {
+    match (self.command(), self.address()) {
+      (CMD_RAM_READ, address) => self.ram[address as usize],
+      (CMD_RTC, RTC_MINUTE_READ) => to_bcd(self.clock.minutes % 60),
+      (CMD_RTC, RTC_HOUR_READ) => to_bcd(self.clock.minutes / 60),
+      _ => 0,
+    }
+  }
+}
+
+impl Mapper for Tama5 {
+  fn read_rom(&self, addr: u16) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr as usize),
+      _ => read_banked(&self.rom, self.rom_bank_n(), ROM_BANK_SIZE, addr as usize - 0x4000),
+    }
+  }
+  fn write_control(&mut self, _addr: u16, _value: u8) {}
+  fn read_ram(&self, addr: u16) -> u8 {
+    if addr & 1 > 0 { return 0xFF; }
+    match self.reg {
+      REG_ACTIVE => 0xF1,
+      REG_READ_LO => 0xF0 | self.result() & 0xF,
+      REG_READ_HI => 0xF0 | self.result() >> 4,
+      _ => 0xFF,
+    }
+  }
+  fn write_ram(&mut self, addr: u16, value: u8) {
+    if addr & 1 > 0 {
+      self.reg = value & 0xF;
+      return;
+    }
+    if let Some(register) = self.registers.get_mut(self.reg as usize) {
+      *register = value & 0xF;
+      if self.reg == REG_ADDR_LO { self.run_command(); }
+    }
+  }
+
+  /**
+   * RAM followed by the clock footer.
+   */
+  fn save(&self) -> Option<Vec<u8>> {
+    let mut data = self.ram.to_vec();
+    self.clock.save(&mut data);
+    Some(data)
+  }
+  fn load(&mut self, data: &[u8]) {
+    load_ram(&mut self.ram, data);
+    if let Some(footer) = data.get(RAM_SIZE..) { self.clock.load(footer); }
+  }
+  fn accepts_save(&self, len: usize) -> bool {
+    len == RAM_SIZE || len == RAM_SIZE + MinuteClock::FOOTER_SIZE
+  }
+
+  fn tick(&mut self, t_states: u32) { self.clock.tick(t_states); }
+  fn next_tick(&self) -> Option<u32> { Some(self.clock.to_next_second()) }
+
+  fn rom_bank(&self) -> usize { self.rom_bank_n() }
+}
+
+fn from_bcd(value: u8) -> u16 { (value >> 4) as u16 * 10 + (value & 0xF) as u16 }
+fn to_bcd(value: u16) -> u8 { (((value / 10) << 4) | (value % 10)) as u8 }
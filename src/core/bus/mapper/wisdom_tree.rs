@@ -0,0 +1,39 @@
+use super::{Mapper, read_banked};
+
+const BANK_SIZE: usize = 0x8000;
+
+/**
+ * Wisdom Tree's unlicensed mapper. Any write to 0x0000-0x3FFF maps the 32 KiB
+ * bank given by the low byte of its address to all of 0x0000-0x7FFF.
+ */
+#[derive(Clone)]
+pub struct WisdomTree {
+  rom: Vec<u8>,
+  bank: u8,
+}
+
+impl WisdomTree {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self { rom, bank: 0 }
+  }
+
+  fn bank_n(&self) -> usize {
+    self.bank as usize % (self.rom.len() / BANK_SIZE).max(1)
+  }
+}
+
+impl Mapper for WisdomTree {
+  fn read_rom(&self, addr: u16) -> u8 {
+    read_banked(&self.rom, self.bank_n(), BANK_SIZE, addr as usize)
+  }
+  fn write_control(&mut self, addr: u16, _value: u8) {
+    if addr < 0x4000 { self.bank = addr as u8; }
+  }
+  fn read_ram(&self, _addr: u16) -> u8 { 0xFF }
+  fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+  fn save(&self) -> Option<Vec<u8>> { None }
+  fn load(&mut self, _data: &[u8]) {}
+
+  fn rom_bank(&self) -> usize { self.bank_n() }
+}
@@ -6,6 +6,7 @@ pub struct Obj {
   pub attr: u8,
 }
 
+#[derive(Clone)]
 pub struct Oam {
   pub objects: [Obj; 40],
 }
@@ -0,0 +1,69 @@
+/**
+ * Serial port with no link cable plugged in. Transfers on the internal clock
+ * finish as soon as they start, shifting in 0xFF. Ones on an external clock
+ * never finish.
+ *
+ * WARNING: Inaccurate implementation, a transfer really takes 8 bits at
+ * 8192 Hz
+ */
+#[derive(Clone, Default)]
+pub struct Serial {
+  pub sb : u8,
+  pub sc : u8,
+  /**
+   * Bytes sent so far, when capturing. Test ROMs print their results this way.
+   */
+  pub output : Option<Vec<u8>>,
+}
+
+impl Serial {
+  pub fn new() -> Self { Self::default() }
+
+  /**
+   * `off` is the register's offset from SB.
+   */
+  pub fn get(&self, off: u8) -> u8 {
+    match off {
+      0 => self.sb,
+      1 => self.sc | 0b_0111_1110,
+      _ => 0,
+    }
+  }
+  /**
+   * Returns whether a transfer finished, which requests the serial interrupt.
+   */
+  pub fn set(&mut self, off: u8, value: u8) -> bool {
+    match off {
+      0 => self.sb = value,
+      1 => {
+        self.sc = value;
+        if value & 0b_1000_0001 == 0b_1000_0001 {
+          if let Some(output) = &mut self.output { output.push(self.sb); }
+          self.sb = 0xFF;
+          self.sc &= 0b_0111_1111;
+          return true;
+        }
+      }
+      _ => {}
+    }
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Serial;
+
+  #[test]
+  fn internal_clock_transfer_finishes_at_once() {
+    let mut serial = Serial::new();
+    serial.output = Some(vec![]);
+    serial.set(0, b'P');
+    assert!(!serial.set(1, 0b_1000_0000));
+    assert_eq!(serial.get(1), 0b_1111_1110);
+    assert!(serial.set(1, 0b_1000_0001));
+    assert_eq!(serial.get(0), 0xFF);
+    assert_eq!(serial.get(1), 0b_0111_1111);
+    assert_eq!(serial.output.as_deref(), Some(&b"P"[..]));
+  }
+}
@@ -1,59 +1,295 @@
-#[derive(Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Reload {
+  #[default]
+  None,
+  /**
+   * TIMA overflowed this M-cycle and reads 0. Writing TIMA now cancels the
+   * reload and the interrupt.
+   */
+  Pending,
+  /**
+   * TIMA was loaded from TMA this M-cycle. Writes to TIMA are lost, writes to
+   * TMA go through to TIMA as well.
+   */
+  Done,
+}
+
+#[derive(Clone, Default)]
 pub struct Timer {
-  pub div  : u8,
+  /**
+   * The system counter, incremented every T-state. DIV is its upper byte.
+   */
+  pub counter : u16,
   pub tima : u8,
   pub tma  : u8,
   pub tac  : u8,
+  /**
+   * Set when TIMA is reloaded, i.e. the interrupt is due.
+   */
   pub overflow: bool,
-  subdiv: u8,
+  reload: Reload,
+  /**
+   * T-state the timer has been brought up to. It only catches up when it's
+   * accessed or overflows.
+   */
+  synced: u64,
 }
 
 impl Timer {
   pub fn new() -> Self { Self::default() }
 
-  pub fn get(&self, addr_offset: u8) -> u8 {
+  /**
+   * Reads a register as of `t_state`.
+   */
+  pub fn get(&self, addr_offset: u8, t_state: u64) -> u8 {
+    let mut timer = self.clone();
+    timer.sync(t_state);
     match addr_offset {
-      0 => self.div,
-      1 => self.tima,
-      2 => self.tma,
-      3 => self.tac,
+      0 => (timer.counter >> 8) as u8,
+      1 => timer.tima,
+      2 => timer.tma,
+      3 => timer.tac | 0b_1111_1000,
       _ => panic!()
     }
   }
-  pub fn set(&mut self, addr_offset: u8, value: u8) {
+  /**
+   * TIMA counts falling edges of a counter bit ANDed with the enable bit, so
+   * resetting DIV or changing TAC can increment it.
+   */
+  pub fn set(&mut self, addr_offset: u8, value: u8, t_state: u64) {
+    self.sync(t_state);
+    let signal = self.signal();
     match addr_offset {
-      0 => {
-        self.div = 0;
-        self.subdiv = 0;
+      0 => self.counter = 0,
+      1 => match self.reload {
+        Reload::None => self.tima = value,
+        Reload::Pending => {
+          self.tima = value;
+          self.reload = Reload::None;
+        }
+        Reload::Done => {}
+      }
+      2 => {
+        self.tma = value;
+        if self.reload == Reload::Done { self.tima = value; }
       }
-      1 => self.tima = value,
-      2 => self.tma  = value,
-      3 => self.tac  = value,
+      3 => self.tac = value & 0b_111,
       _ => panic!()
     }
+    if signal && !self.signal() { self.increment(); }
   }
 
   /**
-   * Called every T-state.
-   */
-  pub fn tick(&mut self) {
-    self.subdiv += 1;
-    if self.subdiv == 0 { self.div += 1; }
-    if self.tac & 0b_100 > 0 {
-      let increased = match self.tac & 0b_11 {
-        0 => ((self.div as u16) << 8 | self.subdiv as u16) % 1024 == 0,
-        1 => self.subdiv % 16 == 0,
-        2 => self.subdiv % 64 == 0,
-        3 => self.subdiv == 0,
-        _ => unreachable!()
-      };
-      if increased {
-        self.tima += 1;
-        if self.tima == 0 {
-          self.tima = self.tma;
-          self.overflow = true;
-        }
+   * Catches up to `t_state`.
+   */
+  pub fn sync(&mut self, t_state: u64) {
+    if t_state <= self.synced { return; }
+    self.advance(t_state - self.synced);
+    self.synced = t_state;
+  }
+
+  /**
+   * T-state at which TIMA is next reloaded and the interrupt requested, if
+   * it's running.
+   */
+  pub fn next_overflow(&self) -> Option<u64> {
+    let to_overflow = if self.reload == Reload::Pending {
+      4
+    } else {
+      self.m_cycles_to_wrap()? * 4 + 4
+    };
+    Some(self.synced + to_overflow)
+  }
+
+  /**
+   * Advances by `t_states`, a multiple of 4. Only the M-cycles around an
+   * overflow are stepped one by one, the rest is skipped in constant time.
+   */
+  fn advance(&mut self, t_states: u64) {
+    let mut m_cycles = t_states / 4;
+    while m_cycles > 0 {
+      let quiet = match self.reload {
+        Reload::None => self.m_cycles_to_wrap().map_or(m_cycles, |to_wrap| to_wrap - 1),
+        Reload::Pending | Reload::Done => 0,
+      }.min(m_cycles);
+      if quiet > 0 {
+        self.skip(quiet);
+        m_cycles -= quiet;
+      } else {
+        self.tick();
+        m_cycles -= 1;
+      }
+    }
+  }
+
+  /**
+   * M-cycles until the one where TIMA wraps to 0, if it's running.
+   */
+  fn m_cycles_to_wrap(&self) -> Option<u64> {
+    let period = self.period()?;
+    let to_increment = period - self.counter as u64 % period;
+    Some((to_increment + (0xFF - self.tima as u64) * period) / 4)
+  }
+
+  /**
+   * `m_cycles` that don't wrap TIMA, at once.
+   */
+  fn skip(&mut self, m_cycles: u64) {
+    let t_states = m_cycles * 4;
+    if let Some(period) = self.period() {
+      let counter = self.counter as u64;
+      let increments = (counter + t_states) / period - counter / period;
+      self.tima += increments as u8;
+    }
+    self.counter = self.counter.wrapping_add(t_states as u16);
+  }
+
+  /**
+   * One M-cycle. A reload happens the M-cycle after the overflow.
+   */
+  fn tick(&mut self) {
+    self.reload = match self.reload {
+      Reload::Pending => {
+        self.tima = self.tma;
+        self.overflow = true;
+        Reload::Done
       }
+      Reload::None | Reload::Done => Reload::None,
+    };
+    let signal = self.signal();
+    self.counter = self.counter.wrapping_add(4);
+    if signal && !self.signal() { self.increment(); }
+  }
+
+  fn increment(&mut self) {
+    self.tima = self.tima.wrapping_add(1);
+    if self.tima == 0 { self.reload = Reload::Pending; }
+  }
+
+  /**
+   * The counter bit TIMA counts, selected by TAC.
+   */
+  fn bit(&self) -> u32 {
+    [9, 3, 5, 7][self.tac as usize & 0b_11]
+  }
+
+  /**
+   * T-states between TIMA increments, if it's running.
+   */
+  fn period(&self) -> Option<u64> {
+    if self.tac & 0b_100 == 0 { return None; }
+    Some(2 << self.bit())
+  }
+
+  fn signal(&self) -> bool {
+    self.tac & 0b_100 != 0 && self.counter >> self.bit() & 1 != 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Timer;
+
+  /**
+   * A timer at T-state 0 with TAC and TIMA set.
+   */
+  fn timer(tac: u8, tima: u8) -> Timer {
+    let mut timer = Timer::new();
+    timer.set(3, tac, 0);
+    timer.set(1, tima, 0);
+    timer
+  }
+
+  #[test]
+  fn div_counts_every_64_m_cycles() {
+    let mut timer = Timer::new();
+    assert_eq!(timer.get(0, 4 * 63), 0);
+    assert_eq!(timer.get(0, 4 * 64), 1);
+    timer.set(0, 0x55, 4 * 64);
+    assert_eq!(timer.get(0, 4 * 64), 0);
+  }
+
+  #[test]
+  fn tima_counts_at_tac_period() {
+    for (tac, period) in [(0b100, 1024), (0b101, 16), (0b110, 64), (0b111, 256)] {
+      let timer = timer(tac, 0);
+      assert_eq!(timer.get(1, period - 4), 0, "TAC {:03b}", tac);
+      assert_eq!(timer.get(1, period), 1, "TAC {:03b}", tac);
+      assert_eq!(timer.get(1, period * 200), 200, "TAC {:03b}", tac);
+    }
+  }
+
+  #[test]
+  fn div_write_glitch() {
+    // Bit 3 high: resetting it is a falling edge
+    let mut timer = self::timer(0b101, 0);
+    timer.set(0, 0, 8);
+    assert_eq!(timer.get(1, 8), 1);
+    // Bit 3 low
+    let mut timer = self::timer(0b101, 0);
+    timer.set(0, 0, 4);
+    assert_eq!(timer.get(1, 4), 0);
+  }
+
+  #[test]
+  fn tac_write_glitch() {
+    // Disabling while the selected bit is high
+    let mut timer = self::timer(0b101, 0);
+    timer.set(3, 0b001, 8);
+    assert_eq!(timer.get(1, 8), 1);
+    // Switching from bit 3, high, to bit 9, low
+    let mut timer = self::timer(0b101, 0);
+    timer.set(3, 0b100, 8);
+    assert_eq!(timer.get(1, 8), 1);
+  }
+
+  #[test]
+  fn reload_is_delayed_one_m_cycle() {
+    let mut timer = timer(0b101, 0xFF);
+    timer.set(2, 0x42, 0);
+    assert_eq!(timer.next_overflow(), Some(20));
+    timer.sync(16);
+    assert_eq!(timer.get(1, 16), 0);
+    assert!(!timer.overflow);
+    timer.sync(20);
+    assert_eq!(timer.get(1, 20), 0x42);
+    assert!(timer.overflow);
+  }
+
+  #[test]
+  fn tima_write_cancels_pending_reload() {
+    let mut timer = timer(0b101, 0xFF);
+    timer.set(2, 0x42, 0);
+    timer.set(1, 0x10, 16);
+    timer.sync(20);
+    assert_eq!(timer.get(1, 20), 0x10);
+    assert!(!timer.overflow);
+  }
+
+  #[test]
+  fn tima_write_during_reload_is_ignored() {
+    let mut timer = timer(0b101, 0xFF);
+    timer.set(2, 0x42, 0);
+    timer.set(1, 0x10, 20);
+    assert_eq!(timer.get(1, 20), 0x42);
+    // TMA goes through to TIMA during the reload
+    timer.set(2, 0x33, 20);
+    assert_eq!(timer.get(1, 20), 0x33);
+    timer.set(1, 0x10, 24);
+    assert_eq!(timer.get(1, 24), 0x10);
+  }
+
+  #[test]
+  fn next_overflow_matches_stepping() {
+    for tac in 0b100..=0b111 {
+      let mut timer = timer(tac, 0xF0);
+      timer.sync(40);
+      let overflow = timer.next_overflow().unwrap();
+      timer.sync(overflow - 4);
+      assert!(!timer.overflow, "TAC {:03b}", tac);
+      timer.sync(overflow);
+      assert!(timer.overflow, "TAC {:03b}", tac);
     }
+    assert_eq!(timer(0b001, 0xF0).next_overflow(), None);
   }
 }
@@ -0,0 +1,460 @@
+mod bootleg;
+
+use std::{fmt, str::FromStr};
+
+use super::error::EmuError;
+
+pub use self::bootleg::{sachen_scramble, BootlegRule};
+
+pub const HEADER_END: usize = 0x150;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapperType {
+  ROM, MBC1, MBC2, MMM01, MBC3, MBC5, MBC6, MBC7, PocketCamera, TAMA5, HuC3, HuC1,
+  WisdomTree, SachenMMC1, SachenMMC2,
+}
+
+impl MapperType {
+  pub const ALL: [MapperType; 15] = {
+    use MapperType::*;
+    [ROM, MBC1, MBC2, MMM01, MBC3, MBC5, MBC6, MBC7, PocketCamera, TAMA5, HuC3, HuC1,
+      WisdomTree, SachenMMC1, SachenMMC2]
+  };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CgbSupport { None, Enhanced, Only }
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Licensee {
+  Old(u8),
+  New(String),
+}
+
+/**
+ * Decoded cartridge header (0x0100-0x014F).
+ */
+#[derive(Clone, Debug)]
+pub struct Cartridge {
+  pub title: String,
+  pub manufacturer_code: Option<String>,
+  pub cgb: CgbSupport,
+  pub sgb: bool,
+  pub licensee: Licensee,
+  pub cart_type: u8,
+  /**
+   * `None` if `cart_type` is not a known controller. For unlicensed carts it
+   * may be guessed from the ROM instead, see `bootleg_rule`.
+   */
+  pub mapper: Option<MapperType>,
+  /**
+   * How `mapper` was guessed, if it was.
+   */
+  pub bootleg_rule: Option<BootlegRule>,
+  pub rom_size: usize,
+  pub ram_size: usize,
+  pub battery: bool,
+  pub rtc: bool,
+  pub rumble: bool,
+  pub version: u8,
+  pub header_checksum: u8,
+  pub header_checksum_ok: bool,
+  pub global_checksum: u16,
+  pub global_checksum_ok: bool,
+}
+
+impl Cartridge {
+  pub fn parse(rom: &[u8]) -> Result<Self, EmuError> {
+    if rom.len() < HEADER_END {
+      return Err(EmuError::TruncatedRom { expected: HEADER_END, actual: rom.len() });
+    }
+    let header_offset = mmm01_menu_offset(rom).unwrap_or(0);
+    let sachen = bootleg::is_sachen(rom);
+    let unscrambled: Vec<u8>;
+    let header = if sachen {
+      unscrambled = (0..HEADER_END).map(|addr| rom[sachen_scramble(addr)]).collect();
+      &unscrambled
+    } else {
+      &rom[header_offset..]
+    };
+    if header[0x148] > 8 {
+      return Err(EmuError::BadHeader { addr: 0x148, value: header[0x148] });
+    }
+    let rom_size = 0x8000 << header[0x148];
+    if rom.len() < rom_size {
+      return Err(EmuError::TruncatedRom { expected: rom_size, actual: rom.len() });
+    }
+    let ram_size = match header[0x149] {
+      0 | 1 => 0,
+      2 => 0x2000,
+      3 => 0x8000,
+      4 => 0x20000,
+      5 => 0x10000,
+      value => return Err(EmuError::BadHeader { addr: 0x149, value }),
+    };
+    let cgb = match header[0x143] {
+      0xC0 => CgbSupport::Only,
+      flag if flag & 0x80 > 0 => CgbSupport::Enhanced,
+      _ => CgbSupport::None,
+    };
+    let manufacturer_code = &header[0x13F..0x143];
+    let has_manufacturer_code = cgb != CgbSupport::None
+      && manufacturer_code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    let (title, manufacturer_code) = match (cgb, has_manufacturer_code) {
+      (_, true) => (&header[0x134..0x13F], Some(decode_ascii(manufacturer_code))),
+      (CgbSupport::None, false) => (&header[0x134..0x144], None),
+      (_, false) => (&header[0x134..0x143], None),
+    };
+    let licensee = match header[0x14B] {
+      0x33 => Licensee::New(decode_ascii(&header[0x144..0x146])),
+      code => Licensee::Old(code),
+    };
+    let cart_type = header[0x147];
+    let (mapper, battery, rtc, rumble) = decode_cart_type(cart_type);
+    let header_checksum = header[0x14D];
+    let global_checksum = (header[0x14E] as u16) << 8 | header[0x14F] as u16;
+    let title = decode_ascii(title);
+    let bootleg = bootleg::detect(rom, header, sachen);
+    Ok(Self {
+      title,
+      manufacturer_code,
+      cgb,
+      sgb: header[0x146] == 0x03 && header[0x14B] == 0x33,
+      licensee,
+      cart_type,
+      mapper: bootleg.map(|(mapper, _)| mapper).or(mapper),
+      bootleg_rule: bootleg.map(|(_, rule)| rule),
+      rom_size,
+      ram_size,
+      battery,
+      rtc,
+      rumble,
+      version: header[0x14C],
+      header_checksum,
+      header_checksum_ok: calc_header_checksum(header) == header_checksum,
+      global_checksum,
+      global_checksum_ok: calc_global_checksum(rom, header_offset) == global_checksum,
+    })
+  }
+}
+
+impl Licensee {
+  pub fn name(&self) -> Option<&'static str> {
+    match self {
+      Licensee::Old(code) => old_licensee_name(*code),
+      Licensee::New(code) => new_licensee_name(code),
+    }
+  }
+}
+
+impl fmt::Display for MapperType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      MapperType::ROM => "ROM",
+      MapperType::MBC1 => "MBC1",
+      MapperType::MBC2 => "MBC2",
+      MapperType::MMM01 => "MMM01",
+      MapperType::MBC3 => "MBC3",
+      MapperType::MBC5 => "MBC5",
+      MapperType::MBC6 => "MBC6",
+      MapperType::MBC7 => "MBC7",
+      MapperType::PocketCamera => "Pocket Camera",
+      MapperType::TAMA5 => "TAMA5",
+      MapperType::HuC3 => "HuC3",
+      MapperType::HuC1 => "HuC1",
+      MapperType::WisdomTree => "Wisdom Tree",
+      MapperType::SachenMMC1 => "Sachen MMC1",
+      MapperType::SachenMMC2 => "Sachen MMC2",
+    })
+  }
+}
+
+/**
+ * Parses the `Display` name, ignoring case, spaces and dashes.
+ */
+impl FromStr for MapperType {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let normalize = |name: &str| name
+      .chars()
+      .filter(|c| c.is_ascii_alphanumeric())
+      .collect::<String>()
+      .to_ascii_lowercase();
+    let name = normalize(s);
+    MapperType::ALL.into_iter()
+      .find(|mapper| normalize(&mapper.to_string()) == name)
+      .ok_or_else(|| format!("Unknown mapper {}", s))
+  }
+}
+
+impl fmt::Display for Licensee {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Licensee::Old(code) => write!(f, "{:02X}", code)?,
+      Licensee::New(code) => write!(f, "{}", code)?,
+    }
+    match self.name() {
+      Some(name) => write!(f, " ({})", name),
+      None => Ok(()),
+    }
+  }
+}
+
+/**
+ * Returns (mapper, battery, RTC, rumble).
+ */
+fn decode_cart_type(cart_type: u8) -> (Option<MapperType>, bool, bool, bool) {
+  use MapperType::*;
+  match cart_type {
+    0x00 => (Some(ROM), false, false, false),
+    0x01 | 0x02 => (Some(MBC1), false, false, false),
+    0x03 => (Some(MBC1), true, false, false),
+    0x05 => (Some(MBC2), false, false, false),
+    0x06 => (Some(MBC2), true, false, false),
+    0x08 => (Some(ROM), false, false, false),
+    0x09 => (Some(ROM), true, false, false),
+    0x0B | 0x0C => (Some(MMM01), false, false, false),
+    0x0D => (Some(MMM01), true, false, false),
+    0x0F | 0x10 => (Some(MBC3), true, true, false),
+    0x11 | 0x12 => (Some(MBC3), false, false, false),
+    0x13 => (Some(MBC3), true, false, false),
+    0x19 | 0x1A => (Some(MBC5), false, false, false),
+    0x1B => (Some(MBC5), true, false, false),
+    0x1C | 0x1D => (Some(MBC5), false, false, true),
+    0x1E => (Some(MBC5), true, false, true),
+    0x20 => (Some(MBC6), true, false, false),
+    0x22 => (Some(MBC7), true, false, true),
+    0xFC => (Some(PocketCamera), true, false, false),
+    0xFD => (Some(TAMA5), true, true, false),
+    0xFE => (Some(HuC3), true, true, false),
+    0xFF => (Some(HuC1), true, false, false),
+    _ => (None, false, false, false),
+  }
+}
+
+fn calc_header_checksum(rom: &[u8]) -> u8 {
+  rom[0x134..=0x14C].iter()
+    .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+/**
+ * MMM01 compilations keep the menu, and the header that describes the whole
+ * cartridge, in the last 32 KiB. The header at the start is the first game's.
+ */
+pub fn mmm01_menu_offset(rom: &[u8]) -> Option<usize> {
+  if rom.len() < 0x10000 || !rom.len().is_multiple_of(0x8000) { return None; }
+  let offset = rom.len() - 0x8000;
+  matches!(rom[offset + 0x147], 0x0B..=0x0D).then_some(offset)
+}
+
+fn calc_global_checksum(rom: &[u8], header_offset: usize) -> u16 {
+  rom.iter().enumerate()
+    .filter(|&(addr, _)| addr != header_offset + 0x14E && addr != header_offset + 0x14F)
+    .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16))
+}
+
+fn decode_ascii(bytes: &[u8]) -> String {
+  bytes.iter()
+    .take_while(|&&c| c != 0)
+    .map(|&c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' })
+    .collect::<String>()
+    .trim_end()
+    .to_string()
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+  Some(match code {
+    "00" => "None",
+    "01" => "Nintendo R&D1",
+    "08" => "Capcom",
+    "13" => "Electronic Arts",
+    "18" => "Hudson Soft",
+    "19" => "b-ai",
+    "20" => "KSS",
+    "22" => "Planning Office WADA",
+    "24" => "PCM Complete",
+    "25" => "San-X",
+    "28" => "Kemco",
+    "29" => "SETA Corporation",
+    "30" => "Viacom",
+    "31" => "Nintendo",
+    "32" => "Bandai",
+    "33" => "Ocean Software/Acclaim Entertainment",
+    "34" => "Konami",
+    "35" => "HectorSoft",
+    "37" => "Taito",
+    "38" => "Hudson Soft",
+    "39" => "Banpresto",
+    "41" => "Ubi Soft",
+    "42" => "Atlus",
+    "44" => "Malibu Interactive",
+    "46" => "Angel",
+    "47" => "Bullet-Proof Software",
+    "49" => "Irem",
+    "50" => "Absolute",
+    "51" => "Acclaim Entertainment",
+    "52" => "Activision",
+    "53" => "Sammy USA Corporation",
+    "54" => "Konami",
+    "55" => "Hi Tech Expressions",
+    "56" => "LJN",
+    "57" => "Matchbox",
+    "58" => "Mattel",
+    "59" => "Milton Bradley Company",
+    "60" => "Titus Interactive",
+    "61" => "Virgin Games Ltd.",
+    "64" => "Lucasfilm Games",
+    "67" => "Ocean Software",
+    "69" => "Electronic Arts",
+    "70" => "Infogrames",
+    "71" => "Interplay Entertainment",
+    "72" => "Broderbund",
+    "73" => "Sculptured Software",
+    "75" => "The Sales Curve Limited",
+    "78" => "THQ",
+    "79" => "Accolade",
+    "80" => "Misawa Entertainment",
+    "83" => "lozc",
+    "86" => "Tokuma Shoten",
+    "87" => "Tsukuda Original",
+    "91" => "Chunsoft Co.",
+    "92" => "Video System",
+    "93" => "Ocean Software/Acclaim Entertainment",
+    "95" => "Varie",
+    "96" => "Yonezawa/s'pal",
+    "97" => "Kaneko",
+    "99" => "Pack-In-Video",
+    "9H" => "Bottom Up",
+    "A4" => "Konami (Yu-Gi-Oh!)",
+    "BL" => "MTO",
+    "DK" => "Kodansha",
+    _ => return None,
+  })
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+  Some(match code {
+    0x00 => "None",
+    0x01 | 0x31 => "Nintendo",
+    0x08 | 0x38 => "Capcom",
+    0x09 => "HOT-B",
+    0x0A | 0xE0 => "Jaleco",
+    0x0B => "Coconuts Japan",
+    0x0C | 0x6E => "Elite Systems",
+    0x13 | 0x69 => "Electronic Arts",
+    0x18 => "Hudson Soft",
+    0x19 => "ITC Entertainment",
+    0x1A => "Yanoman",
+    0x1D => "Japan Clary",
+    0x1F | 0x4A | 0x61 => "Virgin Games Ltd.",
+    0x24 => "PCM Complete",
+    0x25 => "San-X",
+    0x28 => "Kemco",
+    0x29 => "SETA Corporation",
+    0x30 | 0x70 => "Infogrames",
+    0x32 | 0xA2 | 0xB2 => "Bandai",
+    0x34 | 0xA4 => "Konami",
+    0x35 => "HectorSoft",
+    0x39 | 0x9D | 0xD9 => "Banpresto",
+    0x3C => "Entertainment Interactive",
+    0x3E => "Gremlin",
+    0x41 => "Ubi Soft",
+    0x42 | 0xEB => "Atlus",
+    0x44 | 0x4D => "Malibu Interactive",
+    0x46 | 0xCF => "Angel",
+    0x47 => "Spectrum HoloByte",
+    0x49 => "Irem",
+    0x4F => "U.S. Gold",
+    0x50 => "Absolute",
+    0x51 | 0xB0 => "Acclaim Entertainment",
+    0x52 => "Activision",
+    0x53 => "Sammy USA Corporation",
+    0x54 => "GameTek",
+    0x55 => "Park Place",
+    0x56 | 0xDB | 0xFF => "LJN",
+    0x57 => "Matchbox",
+    0x59 => "Milton Bradley Company",
+    0x5A => "Mindscape",
+    0x5B => "Romstar",
+    0x5C | 0xD6 => "Naxat Soft",
+    0x5D => "Tradewest",
+    0x60 => "Titus Interactive",
+    0x67 => "Ocean Software",
+    0x6F => "Electro Brain",
+    0x71 => "Interplay Entertainment",
+    0x72 | 0xAA => "Broderbund",
+    0x73 => "Sculptured Software",
+    0x75 => "The Sales Curve Limited",
+    0x78 => "THQ",
+    0x79 => "Accolade",
+    0x7A => "Triffix Entertainment",
+    0x7C => "MicroProse",
+    0x7F | 0xC2 => "Kemco",
+    0x80 => "Misawa Entertainment",
+    0x83 => "LOZC G.",
+    0x86 | 0xC4 => "Tokuma Shoten",
+    0x8B => "Bullet-Proof Software",
+    0x8C => "Vic Tokai Corp.",
+    0x8E => "Ape Inc.",
+    0x8F => "I'Max",
+    0x91 => "Chunsoft Co.",
+    0x92 => "Video System",
+    0x93 => "Tsuburaya Productions",
+    0x95 | 0xE3 => "Varie",
+    0x96 => "Yonezawa/S'Pal",
+    0x97 => "Kaneko",
+    0x99 => "Arc",
+    0x9A => "Nihon Bussan",
+    0x9B => "Tecmo",
+    0x9C => "Imagineer",
+    0x9F => "Nova",
+    0xA1 => "Hori Electric",
+    0xA6 => "Kawada",
+    0xA7 => "Takara",
+    0xA9 => "Technos Japan",
+    0xAC => "Toei Animation",
+    0xAD => "Toho",
+    0xAF => "Namco",
+    0xB1 => "ASCII Corporation or Nexsoft",
+    0xB4 => "Square Enix",
+    0xB6 => "HAL Laboratory",
+    0xB7 => "SNK",
+    0xB9 | 0xCE => "Pony Canyon",
+    0xBA => "Culture Brain",
+    0xBB => "Sunsoft",
+    0xBD => "Sony Imagesoft",
+    0xBF => "Sammy Corporation",
+    0xC0 | 0xD0 => "Taito",
+    0xC3 => "Square",
+    0xC5 => "Data East",
+    0xC6 => "Tonkin House",
+    0xC8 => "Koei",
+    0xC9 => "UFL",
+    0xCA => "Ultra Games",
+    0xCB => "VAP, Inc.",
+    0xCC => "Use Corporation",
+    0xCD => "Meldac",
+    0xD1 => "SOFEL",
+    0xD2 => "Quest",
+    0xD3 => "Sigma Enterprises",
+    0xD4 => "ASK Kodansha Co.",
+    0xD7 => "Copya System",
+    0xDA => "Tomy",
+    0xDD => "Nippon Computer Systems",
+    0xDE => "Human Ent.",
+    0xDF => "Altron",
+    0xE1 => "Towa Chiki",
+    0xE2 => "Yutaka",
+    0xE5 => "Epoch",
+    0xE7 => "Athena",
+    0xE8 => "Asmik Ace Entertainment",
+    0xE9 => "Natsume",
+    0xEA => "King Records",
+    0xEC => "Epic/Sony Records",
+    0xEE => "IGS",
+    0xF0 => "A Wave",
+    0xF3 => "Extreme Entertainment",
+    _ => return None,
+  })
+}
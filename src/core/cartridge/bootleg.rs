@@ -0,0 +1,98 @@
+use std::fmt;
+
+use super::MapperType;
+
+/**
+ * How a bootleg's mapper was guessed.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootlegRule {
+  /**
+   * The logo only reads right through Sachen's address scrambling.
+   */
+  Sachen,
+  /**
+   * The publisher's name is in the ROM.
+   */
+  WisdomTree,
+  /**
+   * Nothing else matched, but the header is ROM-only while claiming more than
+   * 32 KiB of ROM.
+   */
+  Size,
+}
+
+const LOGO: [u8; 48] = [
+  0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+  0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+  0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+const LOGO_ADDR: usize = 0x104;
+/**
+ * Where Sachen MMC2 carts keep the logo for the CGB boot ROM: the cart starts
+ * out forcing A7 high, before scrambling addresses like MMC1.
+ */
+const MMC2_LOGO_ADDR: usize = 0x184;
+
+/**
+ * Physical address read by a Sachen MMC1 cartridge for `addr` in 0x0100-0x01FF
+ * while it's locked: address bits 0 and 6, and 1 and 4, are swapped.
+ */
+pub fn sachen_scramble(addr: usize) -> usize {
+  let bit = |n: usize| addr >> n & 1;
+  addr & !0b_01010011 | bit(6) | bit(4) << 1 | bit(1) << 4 | bit(0) << 6
+}
+
+/**
+ * Sachen carts pass the boot ROM's logo check only through address
+ * scrambling, so their header reads garbage unless unscrambled.
+ */
+pub fn is_sachen(rom: &[u8]) -> bool {
+  rom.len() >= 0x200
+    && rom[LOGO_ADDR..LOGO_ADDR + LOGO.len()] != LOGO
+    && LOGO.iter().enumerate().all(|(i, &byte)| rom[sachen_scramble(LOGO_ADDR + i)] == byte)
+}
+
+/**
+ * Guesses the mapper of unlicensed carts whose header doesn't name it.
+ * `header` is the unscrambled one. `None` if it seems truthful.
+ */
+pub fn detect(rom: &[u8], header: &[u8], sachen: bool) -> Option<(MapperType, BootlegRule)> {
+  if sachen {
+    let mmc2 = rom[MMC2_LOGO_ADDR..MMC2_LOGO_ADDR + LOGO.len()] == LOGO;
+    let mapper = if mmc2 { MapperType::SachenMMC2 } else { MapperType::SachenMMC1 };
+    return Some((mapper, BootlegRule::Sachen));
+  }
+  let rom_only = matches!(header[0x147], 0x00 | 0xC0);
+  if rom_only && rom.len() > 0x8000 && is_wisdom_tree(rom) {
+    return Some((MapperType::WisdomTree, BootlegRule::WisdomTree));
+  }
+  // Pirate carts often keep the ROM-only type from the game they were built
+  // on, but not its ROM size. MBC1 suits most of them, but only MBC5 reaches
+  // past 2 MiB.
+  if rom_only && header[0x148] > 0 {
+    let mapper = if rom.len() > 0x200000 { MapperType::MBC5 } else { MapperType::MBC1 };
+    return Some((mapper, BootlegRule::Size));
+  }
+  None
+}
+
+impl fmt::Display for BootlegRule {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      BootlegRule::Sachen => "scrambled logo",
+      BootlegRule::WisdomTree => "publisher name",
+      BootlegRule::Size => "ROM size",
+    })
+  }
+}
+
+/**
+ * Wisdom Tree's games carry the publisher's name, sometimes NUL-separated,
+ * near the start of the ROM.
+ */
+fn is_wisdom_tree(rom: &[u8]) -> bool {
+  let start = &rom[..rom.len().min(0x8000)];
+  [&b"WISDOM TREE"[..], &b"WISDOM\0TREE"[..]].iter()
+    .any(|name| start.windows(name.len()).any(|window| window == *name))
+}
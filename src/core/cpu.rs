@@ -1,6 +1,6 @@
-use std::{cell::RefCell, collections::{HashSet, VecDeque}, fs::File, io::Write, rc::Rc};
+use std::{collections::{HashSet, VecDeque}, io::Write};
 
-use super::{bus::Bus, clock::Clock, emu::RegHw};
+use super::{bus::Bus, emu::RegHw, error::EmuError, model::Model};
 
 use Reg::*;
 use Reg16::*;
@@ -30,25 +30,6 @@ static INST_LENGTH: &[u8] = &[
   2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
 ];
 
-static INST_BASE_CYCLES: &[u8] = &[
-  1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
-  1, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
-  2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
-  2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
-  2, 3, 3, 3, 3, 4, 2, 4, 2, 4, 3, 2, 3, 3, 2, 4,
-  2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
-  3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
-  3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
-];
-
 #[derive(Clone, Copy)]
 pub enum Reg { B, C, D, E, H, L, AddrHL, A, AddrBC, AddrDE, F, Imm8(u8) }
 
@@ -61,6 +42,17 @@ pub enum Flag { Z, N, H, C }
 #[derive(Clone, Copy)]
 pub enum Interrupt { VBlank, LCD, Timer, Serial, Joypad }
 
+/**
+ * The rest of the hardware, as seen by the CPU.
+ */
+pub trait Hardware {
+  fn bus(&mut self) -> &mut Bus;
+  /**
+   * Advances everything but the CPU by one M-cycle.
+   */
+  fn cycle(&mut self);
+}
+
 #[derive(Clone, Copy, Default)]
 pub struct Inst {
   pub opcode: u8,
@@ -69,8 +61,6 @@ pub struct Inst {
 }
 
 pub struct Cpu {
-  bus: Rc<RefCell<Bus>>,
-  clock: Rc<RefCell<Clock>>,
   a: u8,
   b: u8,
   c: u8,
@@ -84,38 +74,88 @@ pub struct Cpu {
   ime: bool,
   ei_pending: bool,
   halting: bool,
-  next_inst_t_state: u64,
+  /**
+   * HALT was executed with IME off and an interrupt pending, so the next
+   * opcode fetch doesn't increment PC.
+   */
+  halt_bug: bool,
+  /**
+   * In STOP mode until a joypad line goes low.
+   *
+   * WARNING: Inaccurate implementation, the PPU, DIV and the timer keep
+   * running. On hardware they stay frozen, the LCD blank, until the CPU wakes.
+   */
+  stopped: bool,
+  locked: Option<(u16, u8)>,
   pub inst_log: VecDeque<(u16, Inst)>,
   trace: HashSet<u32>,
-  log: File,
+  /**
+   * Receives every newly visited (banked) PC when set.
+   */
+  pub trace_log: Option<Box<dyn Write + Send>>,
+}
+
+/**
+ * Clones don't inherit the trace log.
+ */
+impl Clone for Cpu {
+  fn clone(&self) -> Self {
+    Self {
+      inst_log: self.inst_log.clone(),
+      trace: self.trace.clone(),
+      trace_log: None,
+      ..*self
+    }
+  }
+}
+
+impl Default for Cpu {
+  fn default() -> Self { Self::new() }
 }
 
 impl Cpu {
-  pub fn new(bus: Rc<RefCell<Bus>>, clock: Rc<RefCell<Clock>>) -> Self {
+  pub fn new() -> Self {
     Self {
-        bus,
-        clock,
-        a: 0x01,
-        b: 0x00,
-        c: 0x13,
-        d: 0x00,
-        e: 0xD8,
-        h: 0x01,
-        l: 0x4D,
-        f: 0b_10000000,
-        sp: 0xFFFE,
-        pc: 0x0100,
+        a: 0,
+        b: 0,
+        c: 0,
+        d: 0,
+        e: 0,
+        h: 0,
+        l: 0,
+        f: 0,
+        sp: 0,
+        pc: 0,
         ime: false,
         ei_pending: false,
         halting: false,
-        next_inst_t_state: 0,
+        halt_bug: false,
+        stopped: false,
+        locked: None,
         inst_log: VecDeque::with_capacity(20),
         trace: HashSet::new(),
-        log: File::create("log.txt").unwrap(),
+        trace_log: None,
     }
   }
 
-  pub fn get_reg(&self, reg: Reg) -> u8 {
+  /**
+   * Sets the registers the way the boot ROM leaves them, at the cartridge
+   * entry point.
+   */
+  pub fn skip_boot(&mut self, model: Model, header_checksum: u8) {
+    let [af, bc, de, hl] = model.post_boot_regs(header_checksum);
+    self.set_reg_16(AF, af);
+    self.set_reg_16(BC, bc);
+    self.set_reg_16(DE, de);
+    self.set_reg_16(HL, hl);
+    self.sp = 0xFFFE;
+    self.pc = 0x0100;
+  }
+
+  /**
+   * Panics on memory operands like `AddrHL`, which live on the bus.
+   */
+  fn get_reg(&self, reg: Reg) -> u8 {
     match reg {
       A => self.a,
       B => self.b,
@@ -125,10 +165,8 @@ impl Cpu {
       H => self.h,
       L => self.l,
       F => self.f,
-      AddrBC => self.bus.borrow().get(self.get_reg_16(BC)),
-      AddrDE => self.bus.borrow().get(self.get_reg_16(DE)),
-      AddrHL => self.bus.borrow().get(self.get_reg_16(HL)),
       Imm8(value) => value,
+      AddrBC | AddrDE | AddrHL => panic!(),
     }
   }
   fn set_reg(&mut self, reg: Reg, value: u8) {
@@ -141,10 +179,7 @@ impl Cpu {
       H => self.h = value,
       L => self.l = value,
       F => self.f = value & 0xF0,
-      AddrBC => self.bus.borrow_mut().set(self.get_reg_16(BC), value),
-      AddrDE => self.bus.borrow_mut().set(self.get_reg_16(DE), value),
-      AddrHL => self.bus.borrow_mut().set(self.get_reg_16(HL), value),
-      Imm8(_) => panic!(),
+      AddrBC | AddrDE | AddrHL | Imm8(_) => panic!(),
     }
   }
   pub fn get_reg_16(&self, reg: Reg16) -> u16 {
@@ -183,226 +218,250 @@ impl Cpu {
     else     { self.set_reg(F, self.get_reg(F) & !get_flag_mask(flag)) }
   }
 
-  fn push(&mut self, value: u16) {
+  /**
+   * Memory accesses take an M-cycle each, and see the rest of the hardware as
+   * it is at the end of it.
+   */
+  fn read(&mut self, addr: u16, hw: &mut impl Hardware) -> u8 {
+    hw.cycle();
+    hw.bus().get(addr)
+  }
+  fn write(&mut self, addr: u16, value: u8, hw: &mut impl Hardware) {
+    hw.cycle();
+    hw.bus().set(addr, value);
+  }
+  fn reg_addr(&self, reg: Reg) -> Option<u16> {
+    match reg {
+      AddrBC => Some(self.get_reg_16(BC)),
+      AddrDE => Some(self.get_reg_16(DE)),
+      AddrHL => Some(self.get_reg_16(HL)),
+      _ => None,
+    }
+  }
+  fn read_reg(&mut self, reg: Reg, hw: &mut impl Hardware) -> u8 {
+    match self.reg_addr(reg) {
+      Some(addr) => self.read(addr, hw),
+      None => self.get_reg(reg),
+    }
+  }
+  fn write_reg(&mut self, reg: Reg, value: u8, hw: &mut impl Hardware) {
+    match self.reg_addr(reg) {
+      Some(addr) => self.write(addr, value, hw),
+      None => self.set_reg(reg, value),
+    }
+  }
+
+  /**
+   * Includes the internal M-cycle that precedes the writes.
+   */
+  fn push(&mut self, value: u16, hw: &mut impl Hardware) {
     let sp = self.get_reg_16(SP);
-    self.bus.borrow_mut().set(sp - 1, (value >> 8) as u8);
-    self.bus.borrow_mut().set(sp - 2, (value >> 0) as u8);
-    self.set_reg_16(SP, sp - 2);
+    hw.cycle();
+    self.write(sp.wrapping_sub(1), (value >> 8) as u8, hw);
+    self.write(sp.wrapping_sub(2), value as u8, hw);
+    self.set_reg_16(SP, sp.wrapping_sub(2));
   }
-  fn pop (&mut self) -> u16 {
+  fn pop (&mut self, hw: &mut impl Hardware) -> u16 {
     let sp = self.get_reg_16(SP);
-    self.set_reg_16(SP, sp + 2);
-    self.bus.borrow().get(sp) as u16
-      | (self.bus.borrow().get(sp + 1) as u16) << 8
+    self.set_reg_16(SP, sp.wrapping_add(2));
+    self.read(sp, hw) as u16
+      | (self.read(sp.wrapping_add(1), hw) as u16) << 8
   }
 
-  fn add(&mut self, reg: Reg) {
+  fn add(&mut self, rhs: u8) {
     let lhs = self.get_reg(A);
-    let rhs = self.get_reg(reg);
-    let result = lhs + rhs;
+    let result = lhs.wrapping_add(rhs);
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, (lhs & 0b_1111) + (rhs & 0b_1111) > 0b_1111);
     self.set_flag(CF, lhs as u16 + rhs as u16 > 0xFF);
   }
-  fn adc(&mut self, reg: Reg) {
+  fn adc(&mut self, rhs: u8) {
     let lhs = self.get_reg(A);
-    let rhs = self.get_reg(reg);
     let carry = self.get_flag(CF) as u8;
-    let result = lhs + rhs + carry;
+    let result = lhs.wrapping_add(rhs).wrapping_add(carry);
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, (lhs & 0b_1111) + (rhs & 0b_1111) + carry > 0b_1111);
     self.set_flag(CF, lhs as u16 + rhs as u16 + carry as u16 > 0xFF);
   }
-  fn sub(&mut self, reg: Reg) {
+  fn sub(&mut self, rhs: u8) {
     let lhs = self.get_reg(A);
-    let rhs = self.get_reg(reg);
-    let result = lhs - rhs;
+    let result = lhs.wrapping_sub(rhs);
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, true);
     self.set_flag(HF, lhs & 0b_1111 < rhs & 0b_1111);
     self.set_flag(CF, (lhs as u16) < rhs as u16);
   }
-  fn sbc(&mut self, reg: Reg) {
+  fn sbc(&mut self, rhs: u8) {
     let lhs = self.get_reg(A);
-    let rhs = self.get_reg(reg);
     let carry = self.get_flag(CF) as u8;
-    let result = lhs - rhs - carry;
+    let result = lhs.wrapping_sub(rhs).wrapping_sub(carry);
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, true);
     self.set_flag(HF, lhs & 0b_1111 < ((rhs & 0b_1111) + carry));
     self.set_flag(CF, (lhs as u16) < rhs as u16 + carry as u16);
   }
-  fn and(&mut self, reg: Reg) {
-    let result = self.get_reg(A) & self.get_reg(reg);
+  fn and(&mut self, rhs: u8) {
+    let result = self.get_reg(A) & rhs;
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, true);
     self.set_flag(CF, false);
   }
-  fn xor(&mut self, reg: Reg) {
-    let result = self.get_reg(A) ^ self.get_reg(reg);
+  fn xor(&mut self, rhs: u8) {
+    let result = self.get_reg(A) ^ rhs;
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, false);
   }
-  fn or (&mut self, reg: Reg) {
-    let result = self.get_reg(A) | self.get_reg(reg);
+  fn or (&mut self, rhs: u8) {
+    let result = self.get_reg(A) | rhs;
     self.set_reg(A, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, false);
   }
-  fn cp (&mut self, reg: Reg) {
+  fn cp (&mut self, rhs: u8) {
     let lhs = self.get_reg(A);
-    let rhs = self.get_reg(reg);
-    let result = lhs - rhs;
+    let result = lhs.wrapping_sub(rhs);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, true);
     self.set_flag(HF, lhs & 0b_1111 < rhs & 0b_1111);
     self.set_flag(CF, lhs < rhs);
   }
 
-  fn inc(&mut self, reg: Reg) {
-    let result = self.get_reg(reg) + 1;
-    self.set_reg(reg, result);
+  fn inc(&mut self, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, result & 0b_1111 == 0b_0000);
+    result
   }
-  fn dec(&mut self, reg: Reg) {
-    let result = self.get_reg(reg) - 1;
-    self.set_reg(reg, result);
+  fn dec(&mut self, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, true);
     self.set_flag(HF, result & 0b_1111 == 0b_1111);
+    result
   }
 
-  fn rlc (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn rlc (&mut self, value: u8) -> u8 {
     let result = value.rotate_left(1);
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_10000000 > 0);
+    result
   }
-  fn rrc (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn rrc (&mut self, value: u8) -> u8 {
     let result = value.rotate_right(1);
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_00000001 > 0);
+    result
   }
-  fn rl  (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn rl  (&mut self, value: u8) -> u8 {
     let result = value << 1 | self.get_flag(CF) as u8;
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_10000000 > 0);
+    result
   }
-  fn rr  (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn rr  (&mut self, value: u8) -> u8 {
     let result = value >> 1 | (self.get_flag(CF) as u8) << 7;
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_00000001 > 0);
+    result
   }
-  fn sla (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn sla (&mut self, value: u8) -> u8 {
     let result = value << 1;
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_10000000 > 0);
+    result
   }
-  fn sra (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn sra (&mut self, value: u8) -> u8 {
     let result = (value as i8 >> 1) as u8;
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_00000001 > 0);
+    result
   }
-  fn swap(&mut self, reg: Reg) {
-    let result = self.get_reg(reg).rotate_left(4);
-    self.set_reg(reg, result);
+  fn swap(&mut self, value: u8) -> u8 {
+    let result = value.rotate_left(4);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, false);
+    result
   }
-  fn srl (&mut self, reg: Reg) {
-    let value = self.get_reg(reg);
+  fn srl (&mut self, value: u8) -> u8 {
     let result = value >> 1;
-    self.set_reg(reg, result);
     self.set_flag(ZF, result == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, false);
     self.set_flag(CF, value & 0b_00000001 > 0);
+    result
   }
 
-  fn bit_test (&mut self, reg: Reg, bit: u8) {
-    self.set_flag(ZF, self.get_reg(reg) & 1 << bit == 0);
+  fn bit_test(&mut self, value: u8, bit: u8) {
+    self.set_flag(ZF, value & 1 << bit == 0);
     self.set_flag(NF, false);
     self.set_flag(HF, true);
   }
-  fn bit_reset(&mut self, reg: Reg, bit: u8) {
-    self.set_reg(reg, self.get_reg(reg) & !(1 << bit));
-  }
-  fn bit_set  (&mut self, reg: Reg, bit: u8) {
-    self.set_reg(reg, self.get_reg(reg) | 1 << bit);
-  }
 
   fn add_16(&mut self, reg: Reg16) {
     let lhs = self.get_reg_16(HL);
     let rhs = self.get_reg_16(reg);
-    let result = lhs + rhs;
+    let result = lhs.wrapping_add(rhs);
     self.set_reg_16(HL, result);
     self.set_flag(NF, false);
     self.set_flag(HF, (lhs & 0xFFF) + (rhs & 0xFFF) > 0xFFF);
     self.set_flag(CF, lhs as u32 + rhs as u32 > 0xFFFF);
   }
   fn inc_16(&mut self, reg: Reg16) {
-    self.set_reg_16(reg, self.get_reg_16(reg) + 0x0001);
+    self.set_reg_16(reg, self.get_reg_16(reg).wrapping_add(1));
   }
   fn dec_16(&mut self, reg: Reg16) {
-    self.set_reg_16(reg, self.get_reg_16(reg) + 0xFFFF);
+    self.set_reg_16(reg, self.get_reg_16(reg).wrapping_sub(1));
   }
 
   fn jr(&mut self, offset: u8) {
     let lhs = self.get_reg_16(PC);
     let rhs = offset as i8 as u16;
-    self.set_reg_16(PC, lhs + rhs);
+    self.set_reg_16(PC, lhs.wrapping_add(rhs));
   }
   fn jp(&mut self, addr: u16) { self.set_reg_16(PC, addr); }
 
-  fn next_byte(&mut self) -> u8 {
+  fn next_byte(&mut self, hw: &mut impl Hardware) -> u8 {
     let pc = self.get_reg_16(PC);
-    let byte = self.bus.borrow().get(pc);
-    self.set_reg_16(PC, pc + 1);
+    let byte = self.read(pc, hw);
+    self.set_reg_16(PC, pc.wrapping_add(1));
     byte
   }
-  fn next_inst(&mut self) -> Inst {
+  fn next_inst(&mut self, hw: &mut impl Hardware) -> Result<Inst, EmuError> {
     let pc = self.get_reg_16(PC);
-    let opcode = self.next_byte();
+    let opcode = if self.halt_bug {
+      self.halt_bug = false;
+      self.read(pc, hw)
+    } else {
+      self.next_byte(hw)
+    };
     let inst = match INST_LENGTH[opcode as usize] {
       1 => Inst {
         opcode,
@@ -410,55 +469,76 @@ impl Cpu {
       },
       2 => Inst {
         opcode,
-        operand: self.next_byte(),
+        operand: self.next_byte(hw),
         ..Default::default()
       },
       3 => Inst {
         opcode,
-        operand_16: self.next_byte() as u16 | (self.next_byte() as u16) << 8,
+        operand_16: self.next_byte(hw) as u16 | (self.next_byte(hw) as u16) << 8,
         ..Default::default()
       },
-      _ => panic!("Last address: 0x{:X}\nOpcode: {:X}", self.pc, opcode),
+      _ => {
+        self.locked = Some((pc, opcode));
+        return Err(EmuError::InvalidOpcode { pc, opcode });
+      }
     };
     self.inst_log.push_back((pc, inst));
     if self.inst_log.len() > 20 { self.inst_log.pop_front(); }
-    inst
+    Ok(inst)
   }
 
-  pub fn tick(&mut self) {
-    if self.clock.borrow().get_t_state() < self.next_inst_t_state { return; }
+  /**
+   * Runs one instruction or interrupt dispatch, or idles for an M-cycle while
+   * halted. `hw` is advanced for every M-cycle spent, including internal ones
+   * without a memory access.
+   *
+   * Once an illegal opcode is fetched the CPU stays locked, and every further
+   * call reports it.
+   */
+  pub fn step(&mut self, hw: &mut impl Hardware) -> Result<(), EmuError> {
+    if let Some((pc, opcode)) = self.locked {
+      hw.cycle();
+      return Err(EmuError::InvalidOpcode { pc, opcode });
+    }
+    if self.stopped {
+      if !hw.bus().gamepad.line_low() {
+        hw.cycle();
+        return Ok(());
+      }
+      self.stopped = false;
+    }
+    let ir = self.pending_interrupts(hw.bus());
+    // HALT ends once an interrupt is pending, even if IME keeps it from being
+    // serviced
     if self.halting {
-      self.delay(1);
-      return;
+      if ir == 0 {
+        hw.cycle();
+        return Ok(());
+      }
+      self.halting = false;
     }
-    let mut ir = self.bus.borrow().get(RegHw::IF as u16);
-    ir &= 0x1F;
-    ir &= self.bus.borrow().get(RegHw::IE as u16);
     if self.ime && ir > 0 {
-      let int_id = 7 - ir.leading_zeros();
-      self.bus.borrow_mut().set(RegHw::IF as u16, ir & !(1 << int_id));
-      self.ime = false;
-      self.push(self.get_reg_16(PC));
-      self.jp(0x40 + int_id as u16 * 8);
-      self.delay(5);
+      self.dispatch_interrupt(hw);
+      return Ok(());
     }
-    if self.ei_pending { 
+    // EI takes effect after the following instruction, which can't be
+    // interrupted
+    if self.ei_pending {
       self.ime = true;
       self.ei_pending = false;
     }
-    if self.pc == 0x5FE6 {
-      self.halting = false;
-    }
-    let bank_pc = if (self.pc as u32) < 0x4000 {
-      self.pc as u32
-    } else {
-      self.bus.borrow().rom_bank as u32 * 0x1000000 + (self.pc as u32)
-    };
-    if !self.trace.contains(&bank_pc) {
-      self.trace.insert(bank_pc);
-      writeln!(self.log, "{:08X}", bank_pc).unwrap();
+    if let Some(log) = &mut self.trace_log {
+      let bank_pc = if (self.pc as u32) < 0x4000 {
+        self.pc as u32
+      } else {
+        hw.bus().mapper.rom_bank() as u32 * 0x1000000 + (self.pc as u32)
+      };
+      if !self.trace.contains(&bank_pc) {
+        self.trace.insert(bank_pc);
+        writeln!(log, "{:08X}", bank_pc)?;
+      }
     }
-    let Inst { opcode, operand, operand_16 } = self.next_inst();
+    let Inst { opcode, operand, operand_16 } = self.next_inst(hw)?;
     match opcode {
       0x00 => {}
       0x01 | 0x11 | 0x21 | 0x31 => {
@@ -467,7 +547,8 @@ impl Cpu {
       0x02 | 0x0A | 0x12 | 0x1A | 0x22 | 0x2A | 0x32 | 0x3A => {
         let reg = [AddrBC, AddrDE, AddrHL, AddrHL][opcode as usize >> 4];
         let (dst, src) = if opcode & 0b_1000 > 0 { (A, reg) } else { (reg, A) };
-        self.set_reg(dst, self.get_reg(src));
+        let value = self.read_reg(src, hw);
+        self.write_reg(dst, value, hw);
         match opcode >> 4 {
           2 => self.inc_16(HL),
           3 => self.dec_16(HL),
@@ -481,46 +562,60 @@ impl Cpu {
         } else {
           self.inc_16(reg);
         };
+        hw.cycle();
       }
       0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
-        self.inc(id_to_reg(opcode >> 3));
+        let reg = id_to_reg(opcode >> 3);
+        let value = self.read_reg(reg, hw);
+        let result = self.inc(value);
+        self.write_reg(reg, result, hw);
       }
       0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
-        self.dec(id_to_reg(opcode >> 3));
+        let reg = id_to_reg(opcode >> 3);
+        let value = self.read_reg(reg, hw);
+        let result = self.dec(value);
+        self.write_reg(reg, result, hw);
       }
       0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
-        self.set_reg(id_to_reg(opcode >> 3), operand);
+        self.write_reg(id_to_reg(opcode >> 3), operand, hw);
       }
       0x07 | 0x0F | 0x17 | 0x1F => {
         let f = [Self::rlc, Self::rrc, Self::rl, Self::rr][opcode as usize >> 3];
-        f(self, A);
+        let result = f(self, self.get_reg(A));
+        self.set_reg(A, result);
         self.set_flag(ZF, false);
       }
       0x08 => {
-        self.bus.borrow_mut()
-          .set(operand_16 + 0, (self.get_reg_16(SP) >> 0) as u8);
-        self.bus.borrow_mut()
-          .set(operand_16 + 1, (self.get_reg_16(SP) >> 8) as u8);
+        let sp = self.get_reg_16(SP);
+        self.write(operand_16, sp as u8, hw);
+        self.write(operand_16.wrapping_add(1), (sp >> 8) as u8, hw);
+      }
+      0x09 | 0x19 | 0x29 | 0x39 => {
+        self.add_16(id_to_reg_16(opcode >> 4));
+        hw.cycle();
+      }
+      0x10 => {
+        // Known inaccuracy: only the CPU stops, see `stopped`
+        hw.bus().set(0xFF04, 0);
+        self.stopped = true;
       }
-      0x09 | 0x19 | 0x29 | 0x39 => self.add_16(id_to_reg_16(opcode >> 4)),
-      0x10 => {},
       0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
         let z = self.get_flag(ZF);
         let c = self.get_flag(CF);
         let cond = [true, !z, z, !c, c][opcode as usize - 0x18 >> 3];
         if cond {
           self.jr(operand);
-          self.delay(1);
+          hw.cycle();
         }
       }
       0x27 => {
         let mut result = self.get_reg(A) as u16;
         if self.get_flag(NF) {
           if self.get_flag(HF) {
-            result -= 6;
+            result = result.wrapping_sub(6);
             if !self.get_flag(CF) { result &= 0xFF; }
           }
-          if self.get_flag(CF) { result -= 0x60; }
+          if self.get_flag(CF) { result = result.wrapping_sub(0x60); }
         } else {
           if self.get_flag(HF) || result & 0xF > 9 { result += 0x06; }
           if self.get_flag(CF) || result > 0x9F    { result += 0x60; }
@@ -546,46 +641,49 @@ impl Cpu {
       }
       0x40..=0x7F => {
         if opcode == 0x76 {
-          self.halting = true;
+          if !self.ime && self.pending_interrupts(hw.bus()) > 0 {
+            self.halt_bug = true;
+          } else {
+            self.halting = true;
+          }
         } else {
           let dst = id_to_reg(opcode - 0x40 >> 3);
           let src = id_to_reg(opcode & 0b_111);
-          self.set_reg(dst, self.get_reg(src));
+          let value = self.read_reg(src, hw);
+          self.write_reg(dst, value, hw);
         }
       }
       0x80..=0xBF => {
         let alu_op = id_to_alu_op(opcode - 0x80 >> 3);
-        alu_op(self, id_to_reg(opcode & 0b_111));
+        let value = self.read_reg(id_to_reg(opcode & 0b_111), hw);
+        alu_op(self, value);
       }
       0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9 => {
-        match opcode {
-          0xC9 => {
-            let stack_top = self.pop();
-            self.set_reg_16(PC, stack_top);
-          }
+        let cond = match opcode {
+          0xC9 => true,
           0xD9 => {
-            self.ei_pending = true;
-            let stack_top = self.pop();
-            self.set_reg_16(PC, stack_top);
+            self.ime = true;
+            true
           }
           _ => {
             let z = self.get_flag(ZF);
             let c = self.get_flag(CF);
-            let cond = [!z, z, !c, c][opcode as usize - 0xC0 >> 3];
-            if cond {
-              let stack_top = self.pop();
-              self.set_reg_16(PC, stack_top);
-              self.delay(3);
-            }
+            hw.cycle();
+            [!z, z, !c, c][(opcode as usize - 0xC0) >> 3]
           }
+        };
+        if cond {
+          let stack_top = self.pop(hw);
+          self.set_reg_16(PC, stack_top);
+          hw.cycle();
         }
       }
       0xC1 | 0xC5 | 0xD1 | 0xD5 | 0xE1 | 0xE5 | 0xF1 | 0xF5 => {
         let reg = [BC, DE, HL, AF][opcode as usize - 0xC0 >> 4];
         if opcode & 0b_100 > 0 {
-          self.push(self.get_reg_16(reg));
+          self.push(self.get_reg_16(reg), hw);
         } else {
-          let value = self.pop();
+          let value = self.pop(hw);
           self.set_reg_16(reg, value);
         }
       }
@@ -603,7 +701,7 @@ impl Cpu {
             self.jp(self.get_reg_16(HL));
           } else {
             self.jp(operand_16);
-            self.delay(1);
+            hw.cycle();
           }
         }
       }
@@ -617,39 +715,38 @@ impl Cpu {
           _ => unreachable!()
         };
         if cond {
-          self.push(self.get_reg_16(PC));
+          self.push(self.get_reg_16(PC), hw);
           self.jp(operand_16);
-          self.delay(3);
         }
       }
       0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
-        id_to_alu_op(opcode - 0xC0 >> 3)(self, Imm8(operand));
+        id_to_alu_op((opcode - 0xC0) >> 3)(self, operand);
       }
       0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
-        self.push(self.get_reg_16(PC));
+        self.push(self.get_reg_16(PC), hw);
         self.jp(opcode as u16 - 0xC7);
       }
       0xCB => {
         let reg = id_to_reg(operand & 0b_111);
-        match operand {
-          0x00..=0x07 => self.rlc (reg),
-          0x08..=0x0F => self.rrc (reg),
-          0x10..=0x17 => self.rl  (reg),
-          0x18..=0x1F => self.rr  (reg),
-          0x20..=0x27 => self.sla (reg),
-          0x28..=0x2F => self.sra (reg),
-          0x30..=0x37 => self.swap(reg),
-          0x38..=0x3F => self.srl (reg),
-          0x40..=0x7F => self.bit_test (reg, operand - 0x40 >> 3),
-          0x80..=0xBF => self.bit_reset(reg, operand - 0x80 >> 3),
-          0xC0..=0xFF => self.bit_set  (reg, operand - 0xC0 >> 3),
-        }
-        if operand & 0b_111 == 6 {
-          match operand {
-            0x40..=0x7F => self.delay(1),
-            _ => self.delay(2)
+        let value = self.read_reg(reg, hw);
+        let bit = operand >> 3 & 0b_111;
+        let result = match operand {
+          0x00..=0x07 => self.rlc (value),
+          0x08..=0x0F => self.rrc (value),
+          0x10..=0x17 => self.rl  (value),
+          0x18..=0x1F => self.rr  (value),
+          0x20..=0x27 => self.sla (value),
+          0x28..=0x2F => self.sra (value),
+          0x30..=0x37 => self.swap(value),
+          0x38..=0x3F => self.srl (value),
+          0x40..=0x7F => {
+            self.bit_test(value, bit);
+            return Ok(());
           }
-        }
+          0x80..=0xBF => value & !(1 << bit),
+          0xC0..=0xFF => value | 1 << bit,
+        };
+        self.write_reg(reg, result, hw);
       }
       0xE0 | 0xE2 | 0xEA | 0xF0 | 0xF2 | 0xFA => {
         let addr = match opcode & 0b_1111 {
@@ -659,10 +756,10 @@ impl Cpu {
           _ => unreachable!()
         };
         if opcode & 0b_10000 > 0 {
-          let value = self.bus.borrow().get(addr);
+          let value = self.read(addr, hw);
           self.set_reg(A, value);
         } else {
-          self.bus.borrow_mut().set(addr, self.get_reg(A));
+          self.write(addr, self.get_reg(A), hw);
         }
       }
       0xE8 | 0xF8 => {
@@ -673,26 +770,68 @@ impl Cpu {
         self.set_flag(NF, false);
         self.set_flag(HF, (sp & 0x0F) + (operand as u16 & 0x0F) > 0x0F);
         self.set_flag(CF, (sp & 0xFF) + (operand as u16 & 0xFF) > 0xFF);
+        hw.cycle();
+        if opcode == 0xE8 { hw.cycle(); }
+      }
+      0xF3 => {
+        self.ime = false;
+        self.ei_pending = false;
       }
-      0xF3 => self.ime = false,
-      0xFB => self.ei_pending = true,
-      0xF9 => self.set_reg_16(SP, self.get_reg_16(HL)),
-      _ => self.invalid_opcode()
+      0xFB => if !self.ime { self.ei_pending = true; }
+      0xF9 => {
+        self.set_reg_16(SP, self.get_reg_16(HL));
+        hw.cycle();
+      }
+      _ => unreachable!()
     }
-    self.delay(INST_BASE_CYCLES[opcode as usize]);
+    Ok(())
   }
 
-  pub fn int_req(&mut self, int: Interrupt) {
-    let mut bus = self.bus.borrow_mut();
-    let value = bus.get(RegHw::IF as u16) | 1 << int as u8;
-    bus.set(RegHw::IF as u16, value);
-    if bus.get(RegHw::IE as u16) & value > 0 { self.halting = false; }
+  /**
+   * Takes 5 M-cycles. The interrupt to service is only picked after the upper
+   * byte of PC is pushed: if that write lands on IE and disables every pending
+   * interrupt, dispatch is cancelled and execution continues at 0x0000.
+   */
+  fn dispatch_interrupt(&mut self, hw: &mut impl Hardware) {
+    self.ime = false;
+    let pc = self.get_reg_16(PC);
+    let sp = self.get_reg_16(SP);
+    hw.cycle();
+    hw.cycle();
+    self.write(sp.wrapping_sub(1), (pc >> 8) as u8, hw);
+    let ie = hw.bus().get(RegHw::IE as u16);
+    self.write(sp.wrapping_sub(2), pc as u8, hw);
+    self.set_reg_16(SP, sp.wrapping_sub(2));
+    let int_flag = hw.bus().get(RegHw::IF as u16);
+    let ir = int_flag & ie & 0x1F;
+    if ir > 0 {
+      let int_id = ir.trailing_zeros();
+      hw.bus().set(RegHw::IF as u16, int_flag & !(1 << int_id));
+      self.jp(0x40 + int_id as u16 * 8);
+    } else {
+      self.jp(0x0000);
+    }
+    hw.cycle();
   }
-  fn delay(&mut self, m_cycle: u8) {
-    self.next_inst_t_state += m_cycle as u64 * 4;
+
+  /**
+   * Whether the CPU is halted or stopped with nothing to wake it yet, so the
+   * rest of the hardware can run ahead without it.
+   */
+  pub fn idle(&self, bus: &Bus) -> bool {
+    self.locked.is_none() && (
+      self.stopped && !bus.gamepad.line_low()
+      || self.halting && self.pending_interrupts(bus) == 0
+    )
   }
 
-  fn invalid_opcode(&self) {}
+  /**
+   * Requested and enabled interrupts, whether or not IME allows servicing
+   * them.
+   */
+  fn pending_interrupts(&self, bus: &Bus) -> u8 {
+    bus.get(RegHw::IF as u16) & bus.get(RegHw::IE as u16) & 0x1F
+  }
 }
 
 fn get_flag_mask(flag: Flag) -> u8 {
@@ -726,7 +865,7 @@ fn id_to_reg_16(id: u8) -> Reg16 {
     _ => panic!()
   }
 }
-fn id_to_alu_op(id: u8) -> fn(&mut Cpu, Reg) {
+fn id_to_alu_op(id: u8) -> fn(&mut Cpu, u8) {
   match id {
     0 => Cpu::add,
     1 => Cpu::adc,
@@ -741,5 +880,251 @@ fn id_to_alu_op(id: u8) -> fn(&mut Cpu, Reg) {
 }
 
 fn add_u16_i8(lhs: u16, rhs: i8) -> u16 {
-  (lhs as i16 + rhs as i16) as u16
+  lhs.wrapping_add_signed(rhs as i16)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /**
+   * Gets the number of the current M-cycle, so a read from it tells when the
+   * read happened.
+   */
+  const STAMP: u16 = 0xFF80;
+  /**
+   * Writes here are recorded with the M-cycle they happened on.
+   */
+  const WATCHED: u16 = 0xC100;
+  const WATCHED_LEN: usize = 0x20;
+  const STACK: u16 = WATCHED + WATCHED_LEN as u16;
+
+  struct TestHw {
+    bus: Bus,
+    m_cycles: u8,
+    watched: [u8; WATCHED_LEN],
+    writes: Vec<(u8, u16)>,
+  }
+
+  impl TestHw {
+    /**
+     * Records writes made on the last M-cycle.
+     */
+    fn record_writes(&mut self) {
+      for (i, old) in self.watched.iter_mut().enumerate() {
+        let addr = WATCHED + i as u16;
+        let value = self.bus.get(addr);
+        if value != *old {
+          self.writes.push((self.m_cycles, addr));
+          *old = value;
+        }
+      }
+    }
+  }
+
+  impl Hardware for TestHw {
+    fn bus(&mut self) -> &mut Bus { &mut self.bus }
+    fn cycle(&mut self) {
+      self.record_writes();
+      self.m_cycles += 1;
+      self.bus.set(STAMP, self.m_cycles);
+    }
+  }
+
+  /**
+   * A CPU about to run `code` from 0x0100, with only the Z flag set, HL
+   * pointing at `STAMP` and the stack just above `WATCHED`.
+   */
+  fn setup(code: &[u8]) -> (Cpu, TestHw) {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + code.len()].copy_from_slice(code);
+    let bus = Bus::new(rom, None, Model::default(), None).unwrap();
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x100;
+    cpu.sp = STACK;
+    cpu.set_reg_16(HL, STAMP);
+    cpu.set_flag(ZF, true);
+    let hw = TestHw { bus, m_cycles: 0, watched: [0; WATCHED_LEN], writes: vec![] };
+    (cpu, hw)
+  }
+
+  /**
+   * Steps once, returning the M-cycles taken.
+   */
+  fn step(cpu: &mut Cpu, hw: &mut TestHw) -> u8 {
+    hw.m_cycles = 0;
+    hw.writes.clear();
+    cpu.step(hw).unwrap();
+    hw.record_writes();
+    hw.m_cycles
+  }
+
+  /**
+   * M-cycles per opcode, with branches not taken. 0 for illegal opcodes.
+   */
+  static M_CYCLES: &[u8] = &[
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+  ];
+
+  #[test]
+  fn instruction_m_cycles() {
+    for opcode in 0..=0xFF {
+      // STOP and HALT don't finish in one step, 0xCB is tested below
+      if M_CYCLES[opcode as usize] == 0 || matches!(opcode, 0x10 | 0x76 | 0xCB) { continue; }
+      // Z set and C clear
+      let expected = match opcode {
+        0x20 | 0x38 | 0xC0 | 0xD8 => 2,
+        0x28 | 0x30 | 0xC2 | 0xDA | 0xC4 | 0xDC => 3,
+        0xCA | 0xD2 => 4,
+        0xC8 | 0xD0 => 5,
+        0xCC | 0xD4 => 6,
+        _ => M_CYCLES[opcode as usize],
+      };
+      let (mut cpu, mut hw) = setup(&[opcode, 0, 0]);
+      assert_eq!(step(&mut cpu, &mut hw), expected, "opcode {:02X}", opcode);
+    }
+    for opcode in 0..=0xFF {
+      let expected = match opcode {
+        _ if opcode & 0b_111 != 6 => 2,
+        0x40..=0x7F => 3,
+        _ => 4,
+      };
+      let (mut cpu, mut hw) = setup(&[0xCB, opcode]);
+      assert_eq!(step(&mut cpu, &mut hw), expected, "opcode CB {:02X}", opcode);
+    }
+  }
+
+  #[test]
+  fn read_m_cycles() {
+    for (code, m_cycle) in [
+      (&[0x7E][..], 2),             // ld a,(hl)
+      (&[0x2A], 2),                 // ld a,(hl+)
+      (&[0x86], 2),                 // add a,(hl)
+      (&[0xF2], 2),                 // ld a,(c)
+      (&[0xF0, 0x80], 3),           // ldh a,(n)
+      (&[0xFA, 0x80, 0xFF], 4),     // ld a,(nn)
+    ] {
+      let (mut cpu, mut hw) = setup(code);
+      cpu.c = STAMP as u8;
+      step(&mut cpu, &mut hw);
+      assert_eq!(cpu.a, m_cycle, "{:02X?}", code);
+    }
+  }
+
+  #[test]
+  fn write_m_cycles() {
+    let (low, high) = (STACK - 2, STACK - 1);
+    for (code, writes) in [
+      (&[0x77][..], &[(2, WATCHED)][..]),            // ld (hl),a
+      (&[0x34], &[(3, WATCHED)]),                    // inc (hl)
+      (&[0xCB, 0xC6], &[(4, WATCHED)]),              // set 0,(hl)
+      (&[0x36, 0xFF], &[(3, WATCHED)]),              // ld (hl),n
+      (&[0xEA, 0x00, 0xC1], &[(4, WATCHED)]),        // ld (nn),a
+      (&[0x08, 0x00, 0xC1], &[(4, WATCHED), (5, WATCHED + 1)]), // ld (nn),sp
+      (&[0xC5], &[(3, high), (4, low)]),             // push bc
+      (&[0xC7], &[(3, high), (4, low)]),             // rst 00
+      (&[0xCD, 0x00, 0x02], &[(5, high), (6, low)]), // call nn
+    ] {
+      let (mut cpu, mut hw) = setup(code);
+      cpu.set_reg_16(HL, WATCHED);
+      cpu.a = 0xFF;
+      cpu.set_reg_16(BC, 0xFFFF);
+      step(&mut cpu, &mut hw);
+      assert_eq!(hw.writes, writes, "{:02X?}", code);
+    }
+  }
+
+  /**
+   * Requests the interrupts in `int_flag` with `ie` enabled.
+   */
+  fn request(hw: &mut TestHw, int_flag: u8, ie: u8) {
+    hw.bus.set(RegHw::IF as u16, int_flag);
+    hw.bus.set(RegHw::IE as u16, ie);
+  }
+
+  #[test]
+  fn dispatch() {
+    let (mut cpu, mut hw) = setup(&[]);
+    cpu.ime = true;
+    request(&mut hw, 0b_00101, 0b_00100);
+    assert_eq!(step(&mut cpu, &mut hw), 5);
+    assert_eq!(cpu.pc, 0x0050);
+    assert_eq!(cpu.sp, STACK - 2);
+    assert_eq!(hw.bus.get(RegHw::IF as u16) & 0x1F, 0b_00001);
+    assert!(!cpu.ime);
+  }
+
+  #[test]
+  fn ie_push_picks_interrupt() {
+    // The upper byte of PC lands on IE: 0x01 disables the timer interrupt
+    let (mut cpu, mut hw) = setup(&[]);
+    cpu.ime = true;
+    cpu.sp = 0x0000;
+    request(&mut hw, 0b_00100, 0b_00100);
+    assert_eq!(step(&mut cpu, &mut hw), 5);
+    assert_eq!(cpu.pc, 0x0000);
+    assert_eq!(hw.bus.get(RegHw::IE as u16) & 0x1F, 0b_00001);
+    assert_eq!(hw.bus.get(RegHw::IF as u16) & 0x1F, 0b_00100);
+    // and enables VBlank, which is serviced instead
+    let (mut cpu, mut hw) = setup(&[]);
+    cpu.ime = true;
+    cpu.sp = 0x0000;
+    request(&mut hw, 0b_00101, 0b_00100);
+    step(&mut cpu, &mut hw);
+    assert_eq!(cpu.pc, 0x0040);
+    assert_eq!(hw.bus.get(RegHw::IF as u16) & 0x1F, 0b_00100);
+  }
+
+  #[test]
+  fn ei_takes_effect_after_next_instruction() {
+    // ei; nop; nop
+    let (mut cpu, mut hw) = setup(&[0xFB, 0x00, 0x00]);
+    request(&mut hw, 0b_00100, 0b_00100);
+    step(&mut cpu, &mut hw);
+    assert_eq!(cpu.pc, 0x0101);
+    step(&mut cpu, &mut hw);
+    assert_eq!(cpu.pc, 0x0102);
+    step(&mut cpu, &mut hw);
+    assert_eq!(cpu.pc, 0x0050);
+    assert_eq!(hw.bus.get(cpu.sp), 0x02);
+  }
+
+  #[test]
+  fn di_after_ei_keeps_interrupts_off() {
+    // ei; di; nop
+    let (mut cpu, mut hw) = setup(&[0xFB, 0xF3, 0x00]);
+    request(&mut hw, 0b_00100, 0b_00100);
+    for _ in 0..3 { step(&mut cpu, &mut hw); }
+    assert_eq!(cpu.pc, 0x0103);
+    assert!(!cpu.ime);
+  }
+
+  #[test]
+  fn reti_enables_interrupts_at_once() {
+    // reti, returning to 0x0200
+    let (mut cpu, mut hw) = setup(&[0xD9]);
+    cpu.sp = STACK - 2;
+    hw.bus.set(STACK - 1, 0x02);
+    hw.bus.set(STACK - 2, 0x00);
+    request(&mut hw, 0b_00100, 0b_00100);
+    assert_eq!(step(&mut cpu, &mut hw), 4);
+    assert_eq!(cpu.pc, 0x0200);
+    step(&mut cpu, &mut hw);
+    assert_eq!(cpu.pc, 0x0050);
+  }
 }
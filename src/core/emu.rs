@@ -1,10 +1,7 @@
-use std::{cell::RefCell, fs::File, rc::Rc};
+use super::{bus::Bus, cartridge::MapperType, cpu::{Cpu, Hardware, Interrupt}, clock::Clock, error::EmuError,
+  model::Model, ppu::{Ppu, FRAMEBUFFER_SIZE, T_STATES_PER_LINE}, scheduler::{Event, Scheduler}};
 
-use memmap2::{Mmap, MmapMut};
-
-use super::{bus::Bus, cpu::{Cpu, Interrupt}, clock::Clock, ppu::Ppu};
-
-const T_STATES_PER_TICK: u8 = 4;   // Reduce this if accuracy is needed
+const T_STATES_PER_M_CYCLE: u32 = 4;
 
 #[derive(Clone, Copy)]
 pub enum RegHw {
@@ -15,49 +12,270 @@ pub enum RegHw {
   SCX  = 0xFF43,
   LY   = 0xFF44,
   LYC  = 0xFF45,
+  WY   = 0xFF4A,
+  WX   = 0xFF4B,
   IE   = 0xFFFF,
 }
 
+/**
+ * Outcome of `Emu::run_frame` and `Emu::run_cycles`.
+ */
+pub struct RunResult<'a> {
+  /**
+   * Whether the PPU entered VBlank during the run.
+   */
+  pub frame_complete: bool,
+  pub framebuffer: &'a [u8; FRAMEBUFFER_SIZE],
+}
+
+/**
+ * Settings fixed at power-on.
+ */
+#[derive(Clone, Default)]
+pub struct EmuOptions {
+  pub model: Model,
+  /**
+   * Forces a cartridge controller, for carts whose header is wrong.
+   */
+  pub mapper: Option<MapperType>,
+  /**
+   * Boot ROM to run first. Without one, emulation starts from the state it
+   * would leave behind.
+   */
+  pub boot_rom: Option<Vec<u8>>,
+}
+
 pub struct Emu {
-	pub bus: Rc<RefCell<Bus>>,
-	pub clock: Rc<RefCell<Clock>>,
+	pub bus: Bus,
+	pub clock: Clock,
 	pub cpu: Cpu,
   pub ppu: Ppu,
+  scheduler: Scheduler,
+  /**
+   * Called with the new motor state whenever a rumble cartridge toggles it.
+   */
+  pub on_rumble: Option<Box<dyn FnMut(bool) + Send>>,
+  rumble: bool,
 }
 
-impl Emu {
-  pub fn new(rom: Mmap, sram: Option<MmapMut>) -> Self {
-    let bus = Rc::new(RefCell::new(Bus::new(rom, sram)));
-    let clock = Rc::new(RefCell::new(Clock::new()));
+/**
+ * Snapshots the whole machine. Callbacks aren't carried over.
+ */
+impl Clone for Emu {
+  fn clone(&self) -> Self {
     Self {
-      bus: bus.clone(),
-      clock: clock.clone(),
-      cpu: Cpu::new(bus.clone(), clock.clone()),
-      ppu: Ppu::new(bus.clone(), clock.clone()),
+      bus: self.bus.clone(),
+      clock: self.clock.clone(),
+      cpu: self.cpu.clone(),
+      ppu: self.ppu.clone(),
+      scheduler: self.scheduler.clone(),
+      on_rumble: None,
+      rumble: self.rumble,
+    }
+  }
+}
+
+/**
+ * Everything but the CPU, borrowed from `Emu` while the CPU steps.
+ */
+struct Peripherals<'a> {
+  bus: &'a mut Bus,
+  clock: &'a mut Clock,
+  ppu: &'a mut Ppu,
+  scheduler: &'a mut Scheduler,
+  /**
+   * Whether the PPU entered VBlank since these were borrowed.
+   */
+  vblank: bool,
+}
+
+impl Emu {
+  /**
+   * Both buffers are plain bytes, e.g. read from files or generated in memory.
+   * Pass the previous SRAM content, if any, to resume a battery save.
+   */
+  pub fn new(rom: Vec<u8>, sram: Option<Vec<u8>>) -> Result<Self, EmuError> {
+    Self::with_options(rom, sram, EmuOptions::default())
+  }
+
+  pub fn with_options(rom: Vec<u8>, sram: Option<Vec<u8>>, options: EmuOptions)
+  -> Result<Self, EmuError> {
+    let mut bus = Bus::new(rom, sram, options.model, options.mapper)?;
+    let mut cpu = Cpu::new();
+    match options.boot_rom {
+      Some(boot_rom) => bus.map_boot_rom(boot_rom)?,
+      None => {
+        let header_checksum = bus.cartridge.header_checksum;
+        bus.skip_boot();
+        cpu.skip_boot(options.model, header_checksum);
+      }
     }
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(Event::PpuLine, 0);
+    Ok(Self {
+      bus,
+      clock: Clock::new(),
+      cpu,
+      ppu: Ppu::new(),
+      scheduler,
+      on_rumble: None,
+      rumble: false,
+    })
+  }
+
+  /**
+   * Runs one CPU step (see `Cpu::step`) with the rest of the hardware kept in
+   * lockstep, and returns whether the PPU entered VBlank meanwhile. While the
+   * CPU is idle, skips straight to the next event that could wake it instead.
+   * The rest of the hardware keeps running after a CPU lock-up, which is
+   * reported as an error.
+   */
+  pub fn tick(&mut self) -> Result<bool, EmuError> {
+    let mut peripherals = Peripherals {
+      bus: &mut self.bus,
+      clock: &mut self.clock,
+      ppu: &mut self.ppu,
+      scheduler: &mut self.scheduler,
+      vblank: false,
+    };
+    let cpu_result = if self.cpu.idle(peripherals.bus) {
+      peripherals.skip();
+      Ok(())
+    } else {
+      self.cpu.step(&mut peripherals)
+    };
+    let vblank = peripherals.vblank;
+    self.poll_rumble();
+    cpu_result.map(|_| vblank)
+  }
+
+  /**
+   * Runs until the PPU enters VBlank, i.e. the framebuffer holds a whole frame.
+   */
+  pub fn run_frame(&mut self) -> Result<RunResult<'_>, EmuError> {
+    while !self.tick()? {}
+    Ok(RunResult { frame_complete: true, framebuffer: &self.ppu.framebuffer })
   }
 
-  pub fn tick(&mut self) {
-    let mut timer_irq = false;
-    self.cpu.tick();
-    self.ppu.tick();
-    if self.ppu.irq_vblank {
-      self.ppu.irq_vblank = false;
-      self.cpu.int_req(Interrupt::VBlank);
+  /**
+   * Runs for at least `t_states` T-states. The framebuffer may be partially
+   * drawn unless `frame_complete` is set.
+   */
+  pub fn run_cycles(&mut self, t_states: u64) -> Result<RunResult<'_>, EmuError> {
+    let target_t_state = self.clock.get_t_state().saturating_add(t_states);
+    let mut frame_complete = false;
+    while self.clock.get_t_state() < target_t_state {
+      frame_complete |= self.tick()?;
     }
-    if self.ppu.irq_lcd {
-      self.ppu.irq_lcd = false;
-      self.cpu.int_req(Interrupt::LCD);
+    Ok(RunResult { frame_complete, framebuffer: &self.ppu.framebuffer })
+  }
+
+  /**
+   * Whether the rumble motor is currently on.
+   */
+  pub fn rumble(&self) -> bool { self.rumble }
+
+  /**
+   * Sets the tilt sensed by cartridges with an accelerometer (MBC7), in g and
+   * clamped to ±1. `x` is positive when tilted right, `y` when tilted towards
+   * the player.
+   */
+  pub fn set_tilt(&mut self, x: f32, y: f32) {
+    self.bus.mapper.set_tilt(x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+  }
+
+  /**
+   * Sets the picture seen by the Game Boy Camera: `SENSOR_WIDTH` x
+   * `SENSOR_HEIGHT` grayscale bytes, row by row, 0 being black.
+   */
+  pub fn set_camera_image(&mut self, image: &[u8]) {
+    self.bus.mapper.set_camera_image(image);
+  }
+
+  /**
+   * Copy of the current cartridge RAM, for persisting battery saves.
+   */
+  pub fn sram(&self) -> Option<Vec<u8>> {
+    self.bus.mapper.save()
+  }
+
+  fn poll_rumble(&mut self) {
+    let rumble = self.bus.mapper.rumble();
+    if rumble == self.rumble { return; }
+    self.rumble = rumble;
+    if let Some(on_rumble) = &mut self.on_rumble { on_rumble(rumble); }
+  }
+}
+
+impl Peripherals<'_> {
+  /**
+   * Advances to the next event that could wake an idle CPU.
+   */
+  fn skip(&mut self) {
+    if self.bus.reschedule { self.reschedule(); }
+    let t_states = self.scheduler.next_deadline().saturating_sub(self.clock.get_t_state())
+      .clamp(1, u32::MAX as u64 / 2) as u32;
+    self.advance(t_states.next_multiple_of(T_STATES_PER_M_CYCLE));
+  }
+
+  /**
+   * Advances by `t_states` and handles the events that fall due.
+   */
+  fn advance(&mut self, t_states: u32) {
+    self.clock.add_t_state(t_states);
+    let t_state = self.clock.get_t_state();
+    self.bus.t_state = t_state;
+    if self.bus.reschedule { self.reschedule(); }
+    while let Some((event, deadline)) = self.scheduler.pop(t_state) {
+      match event {
+        Event::PpuLine => {
+          self.ppu.tick(self.bus);
+          self.scheduler.schedule(Event::PpuLine, deadline + T_STATES_PER_LINE);
+          if self.ppu.irq_vblank {
+            self.ppu.irq_vblank = false;
+            self.bus.request_interrupt(Interrupt::VBlank);
+            self.vblank = true;
+          }
+          if self.ppu.irq_lcd {
+            self.ppu.irq_lcd = false;
+            self.bus.request_interrupt(Interrupt::LCD);
+          }
+        }
+        Event::TimerOverflow => {
+          self.bus.timer.sync(deadline);
+          if self.bus.timer.overflow {
+            self.bus.timer.overflow = false;
+            self.bus.request_interrupt(Interrupt::Timer);
+          }
+          self.reschedule();
+        }
+        Event::MapperClock => {
+          self.bus.sync_mapper(deadline);
+          self.reschedule();
+        }
+      }
     }
-    for _ in 0..T_STATES_PER_TICK {
-      let timer = &mut self.bus.borrow_mut().timer;
-      timer.tick();
-      if timer.overflow {
-        timer.overflow = false;
-        timer_irq = true;
+  }
+
+  /**
+   * Schedules the timer's and the mapper's next events, after a write may have
+   * moved them.
+   */
+  fn reschedule(&mut self) {
+    self.bus.reschedule = false;
+    for (event, t_state) in [
+      (Event::TimerOverflow, self.bus.timer.next_overflow()),
+      (Event::MapperClock, self.bus.next_mapper_tick()),
+    ] {
+      match t_state {
+        Some(t_state) => self.scheduler.schedule(event, t_state),
+        None => self.scheduler.cancel(event),
       }
     }
-    if timer_irq { self.cpu.int_req(Interrupt::Timer); }
-    self.clock.borrow_mut().add_t_state(T_STATES_PER_TICK);
   }
 }
+
+impl Hardware for Peripherals<'_> {
+  fn bus(&mut self) -> &mut Bus { self.bus }
+  fn cycle(&mut self) { self.advance(T_STATES_PER_M_CYCLE); }
+}
@@ -0,0 +1,70 @@
+use std::{error::Error, fmt, io};
+
+use super::model::Model;
+
+#[derive(Debug)]
+pub enum EmuError {
+  /**
+   * Cartridge type (0x0147) that has no mapper implementation.
+   */
+  UnsupportedMapper(u8),
+  /**
+   * ROM is shorter than its header says, or too short to hold a header.
+   */
+  TruncatedRom { expected: usize, actual: usize },
+  /**
+   * Header byte at `addr` has a value with no defined meaning.
+   */
+  BadHeader { addr: u16, value: u8 },
+  /**
+   * The CPU fetched an illegal opcode and locked up.
+   */
+  InvalidOpcode { pc: u16, opcode: u8 },
+  /**
+   * Save data doesn't fit the cartridge, e.g. it belongs to another game.
+   */
+  SaveMismatch { expected: usize, actual: usize },
+  /**
+   * Boot ROM of the wrong size for the model.
+   */
+  BadBootRom { model: Model, expected: usize, actual: usize },
+  /**
+   * Camera input image that can't be decoded.
+   */
+  BadImage(String),
+  Io(io::Error),
+}
+
+impl fmt::Display for EmuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EmuError::UnsupportedMapper(cart_type) =>
+        write!(f, "Unsupported cartridge type 0x{:02X}", cart_type),
+      EmuError::TruncatedRom { expected, actual } =>
+        write!(f, "ROM is truncated: expected {} bytes, got {}", expected, actual),
+      EmuError::BadHeader { addr, value } =>
+        write!(f, "Bad cartridge header: 0x{:02X} at 0x{:04X}", value, addr),
+      EmuError::InvalidOpcode { pc, opcode } =>
+        write!(f, "CPU locked up on illegal opcode 0x{:02X} at 0x{:04X}", opcode, pc),
+      EmuError::SaveMismatch { expected, actual } =>
+        write!(f, "Save data doesn't match the cartridge: expected {} bytes, got {}", expected, actual),
+      EmuError::BadBootRom { model, expected, actual } =>
+        write!(f, "{} boot ROM must be {} bytes, got {}", model, expected, actual),
+      EmuError::BadImage(reason) => write!(f, "Bad image: {}", reason),
+      EmuError::Io(err) => write!(f, "I/O error: {}", err),
+    }
+  }
+}
+
+impl Error for EmuError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      EmuError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for EmuError {
+  fn from(err: io::Error) -> Self { EmuError::Io(err) }
+}
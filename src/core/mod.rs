@@ -1,5 +1,9 @@
 pub mod bus;
+pub mod cartridge;
 pub mod clock;
 pub mod cpu;
 pub mod ppu;
+pub mod scheduler;
 pub mod emu;
+pub mod error;
+pub mod model;
@@ -0,0 +1,109 @@
+use std::{fmt, str::FromStr};
+
+/**
+ * Hardware revision. Besides the CPU registers software uses to tell them
+ * apart, they differ in the state left by the boot ROM and in which I/O
+ * registers exist.
+ *
+ * CGB hardware itself (double speed, banked VRAM/WRAM, color palettes) isn't
+ * emulated, so CGB and AGB only differ from DMG in these.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Model {
+  /**
+   * Early DMG with the original boot ROM.
+   */
+  DMG0,
+  #[default]
+  DMG,
+  /**
+   * Game Boy Pocket.
+   */
+  MGB,
+  SGB,
+  CGB,
+  /**
+   * Game Boy Advance, in CGB mode.
+   */
+  AGB,
+}
+
+/**
+ * Registers that only exist on CGB and AGB: KEY1, VBK, HDMA1-5, RP, BCPS,
+ * BCPD, OCPS, OCPD, OPRI and SVBK. On other models they read 0xFF.
+ */
+const CGB_REGS: &[u16] = &[
+  0xFF4D, 0xFF4F, 0xFF51, 0xFF52, 0xFF53, 0xFF54, 0xFF55, 0xFF56,
+  0xFF68, 0xFF69, 0xFF6A, 0xFF6B, 0xFF6C, 0xFF70,
+];
+
+impl Model {
+  pub const ALL: [Model; 6] = {
+    use Model::*;
+    [DMG0, DMG, MGB, SGB, CGB, AGB]
+  };
+
+  pub fn is_cgb(self) -> bool { matches!(self, Model::CGB | Model::AGB) }
+
+  pub fn boot_rom_size(self) -> usize { if self.is_cgb() { 0x900 } else { 0x100 } }
+
+  pub fn has_reg(self, addr: u16) -> bool { self.is_cgb() || !CGB_REGS.contains(&addr) }
+
+  /**
+   * AF, BC, DE and HL as the boot ROM leaves them. DMG and MGB set H and C
+   * unless the header checksum is 0.
+   */
+  pub fn post_boot_regs(self, header_checksum: u8) -> [u16; 4] {
+    let hc = if header_checksum == 0 { 0x00 } else { 0x30 };
+    match self {
+      Model::DMG0 => [0x0100, 0xFF13, 0x00C1, 0x8403],
+      Model::DMG => [0x0180 | hc, 0x0013, 0x00D8, 0x014D],
+      Model::MGB => [0xFF80 | hc, 0x0013, 0x00D8, 0x014D],
+      Model::SGB => [0x0100, 0x0014, 0x0000, 0xC060],
+      Model::CGB => [0x1180, 0x0000, 0xFF56, 0x000D],
+      Model::AGB => [0x1100, 0x0100, 0xFF56, 0x000D],
+    }
+  }
+
+  /**
+   * I/O registers the boot ROM leaves differently from the DMG.
+   */
+  pub fn post_boot_io(self) -> &'static [(u16, u8)] {
+    match self {
+      Model::DMG0 => &[(0xFF41, 0x81)],
+      Model::DMG | Model::MGB => &[],
+      Model::SGB => &[(0xFF26, 0xF0)],
+      Model::CGB | Model::AGB => &[(0xFF4D, 0x7E), (0xFF4F, 0xFE), (0xFF70, 0xF8)],
+    }
+  }
+
+  /**
+   * Upper byte of the 16-bit divider counter, i.e. DIV, after booting.
+   */
+  pub fn post_boot_div(self) -> u8 {
+    match self {
+      Model::DMG0 => 0x18,
+      Model::DMG | Model::MGB => 0xAB,
+      Model::SGB | Model::CGB | Model::AGB => 0x00,
+    }
+  }
+}
+
+impl fmt::Display for Model {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fmt::Debug::fmt(self, f)
+  }
+}
+
+/**
+ * Parses the `Display` name, ignoring case.
+ */
+impl FromStr for Model {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Model::ALL.into_iter()
+      .find(|model| model.to_string().eq_ignore_ascii_case(s))
+      .ok_or_else(|| format!("Unknown model {}", s))
+  }
+}
@@ -1,40 +1,49 @@
-use std::{rc::Rc, cell::RefCell};
+use super::{bus::{Bus, oam::Obj}, emu::RegHw};
 
-use super::{bus::{Bus, oam::Obj}, clock::Clock, emu::RegHw};
-
-const T_STATES_PER_LINE: u64 = 456;
+pub const T_STATES_PER_LINE: u64 = 456;
 const LINES_PER_FRAME: u8 = 154;
-const SCRN_X: u8 = 160;
-const SCRN_Y: u8 = 144;
+pub const SCRN_X: u8 = 160;
+pub const SCRN_Y: u8 = 144;
+pub const FRAMEBUFFER_SIZE: usize = SCRN_X as usize * SCRN_Y as usize;
 
 enum Palette { BG, OBJ0, OBJ1 }
 
+#[derive(Clone)]
 pub struct Ppu {
-  bus: Rc<RefCell<Bus>>,
-  clock: Rc<RefCell<Clock>>,
-  pub framebuffer: [u8; SCRN_X as usize * SCRN_Y as usize],
+  pub framebuffer: [u8; FRAMEBUFFER_SIZE],
   pub current_line: u8,
-  next_line_t_state: u64,
   pub irq_vblank: bool,
   pub irq_lcd: bool,
+  /**
+   * Line of the window to draw next. Only advances on lines where the window
+   * was drawn.
+   */
+  window_line: u8,
+  /**
+   * Whether LY has matched WY this frame. The window can't show before that.
+   */
+  window_y_reached: bool,
+}
+
+impl Default for Ppu {
+  fn default() -> Self { Self::new() }
 }
 
 impl Ppu {
-  pub fn new(bus: Rc<RefCell<Bus>>, clock: Rc<RefCell<Clock>>) -> Self {
+  pub fn new() -> Self {
     Self {
-      bus,
-      clock,
-      framebuffer: [0; SCRN_X as usize * SCRN_Y as usize],
+      framebuffer: [0; FRAMEBUFFER_SIZE],
       current_line: 0,
-      next_line_t_state: 0,
       irq_vblank: false,
       irq_lcd: false,
+      window_line: 0,
+      window_y_reached: false,
     }
   }
 
-  fn get_palette(&self, palette_type: Palette) -> [u8; 4] {
+  fn get_palette(bus: &Bus, palette_type: Palette) -> [u8; 4] {
     let addr = 0xFF47 + palette_type as u16;
-    let palette_data = self.bus.borrow().get(addr);
+    let palette_data = bus.get(addr);
     [
       palette_data >> 0 & 0b_11,
       palette_data >> 2 & 0b_11,
@@ -42,22 +51,30 @@ impl Ppu {
       palette_data >> 6 & 0b_11,
     ]
   }
-  fn get_bg_offset(&self) -> (u8, u8) {
-    (self.bus.borrow().get(0xFF43), self.bus.borrow().get(0xFF42))
+  fn get_bg_offset(bus: &Bus) -> (u8, u8) {
+    (bus.get(0xFF43), bus.get(0xFF42))
   }
 
   /**
+   * Called at the start of every line, `T_STATES_PER_LINE` apart.
+   *
    * WARNING: Inaccurate implementation
    */
-  pub fn tick(&mut self) {
-    let lcdc = self.bus.borrow().get(RegHw::LCDC as u16);
-    if self.clock.borrow().get_t_state() < self.next_line_t_state { return; }
-    self.next_line_t_state += T_STATES_PER_LINE;
-    self.bus.borrow_mut().set(RegHw::LY as u16, self.current_line);
+  pub fn tick(&mut self, bus: &mut Bus) {
+    let lcdc = bus.get(RegHw::LCDC as u16);
+    bus.set(RegHw::LY as u16, self.current_line);
+    if self.current_line == 0 {
+      self.window_line = 0;
+      self.window_y_reached = false;
+    }
+    if self.current_line == bus.get(RegHw::WY as u16) { self.window_y_reached = true; }
     if self.current_line < SCRN_Y {
       if lcdc >> 7 > 0 {
-        if lcdc >> 0 & 1 > 0 { self.draw_bg(); }
-        if lcdc >> 1 & 1 > 0 { self.draw_obj(); }
+        if lcdc & 1 > 0 {
+          self.draw_bg(bus);
+          if lcdc >> 5 & 1 > 0 { self.draw_window(bus); }
+        }
+        if lcdc >> 1 & 1 > 0 { self.draw_obj(bus); }
       }
     } else if self.current_line == SCRN_Y {
       self.irq_vblank = true;
@@ -66,35 +83,54 @@ impl Ppu {
     if self.current_line >= LINES_PER_FRAME { self.current_line = 0; }
   }
 
-  fn draw_bg(&mut self) {
+  fn draw_bg(&mut self, bus: &Bus) {
     let y = self.current_line;
-    let bus = self.bus.borrow();
     let lcdc = bus.get(RegHw::LCDC as u16);
-    let alt_tiles = lcdc >> 4 & 1 == 0;
-    let (bg_offset_x, bg_offset_y) = self.get_bg_offset();
+    let (bg_offset_x, bg_offset_y) = Self::get_bg_offset(bus);
     let bg_map = lcdc as u16 >> 3 & 1;
-    let bg_palette = self.get_palette(Palette::BG);
+    let bg_palette = Self::get_palette(bus, Palette::BG);
     for x in 0..SCRN_X {
-      let tilemap_x = (bg_offset_x + x) / 8;
-      let tilemap_y = (bg_offset_y + y) / 8;
-      let tilemap_idx = tilemap_y as u16 * 32 + tilemap_x as u16;
-      let mut tile_id = bus.get(0x9800 + bg_map * 0x400 + tilemap_idx) as u16;
-      if alt_tiles && tile_id < 128 { tile_id += 256; }
-      let tile_x = (bg_offset_x + x) % 8;
-      let tile_y = (bg_offset_y + y) % 8;
-      let addr = 0x8000 + tile_id as u16 * 16 + tile_y as u16 * 2;
-      let lsb = bus.get(addr + 0) >> 7 - tile_x & 1;
-      let msb = bus.get(addr + 1) >> 7 - tile_x & 1;
-      let color = bg_palette[(lsb | msb << 1) as usize];
-      self.framebuffer[y as usize * SCRN_X as usize + x as usize] = color;
+      let color_id = Self::get_map_pixel(bus, bg_map,
+        bg_offset_x.wrapping_add(x), bg_offset_y.wrapping_add(y));
+      self.framebuffer[y as usize * SCRN_X as usize + x as usize] = bg_palette[color_id as usize];
+    }
+  }
+  /**
+   * Drawn over the background from (WX - 7, WY), sharing its tile data.
+   */
+  fn draw_window(&mut self, bus: &Bus) {
+    let y = self.current_line;
+    let wx = bus.get(RegHw::WX as u16);
+    if !self.window_y_reached || wx >= SCRN_X + 7 { return; }
+    let lcdc = bus.get(RegHw::LCDC as u16);
+    let window_map = lcdc as u16 >> 6 & 1;
+    let bg_palette = Self::get_palette(bus, Palette::BG);
+    for x in wx.saturating_sub(7)..SCRN_X {
+      let color_id = Self::get_map_pixel(bus, window_map, x + 7 - wx, self.window_line);
+      self.framebuffer[y as usize * SCRN_X as usize + x as usize] = bg_palette[color_id as usize];
     }
+    self.window_line += 1;
+  }
+  /**
+   * Color ID at (`map_x`, `map_y`) of tile map `map`, using the tile data
+   * selected by LCDC bit 4.
+   */
+  fn get_map_pixel(bus: &Bus, map: u16, map_x: u8, map_y: u8) -> u8 {
+    let alt_tiles = bus.get(RegHw::LCDC as u16) >> 4 & 1 == 0;
+    let tilemap_idx = (map_y / 8) as u16 * 32 + (map_x / 8) as u16;
+    let mut tile_id = bus.get(0x9800 + map * 0x400 + tilemap_idx) as u16;
+    if alt_tiles && tile_id < 128 { tile_id += 256; }
+    let (tile_x, tile_y) = (map_x % 8, map_y % 8);
+    let addr = 0x8000 + tile_id * 16 + tile_y as u16 * 2;
+    let lsb = (bus.get(addr) >> (7 - tile_x)) & 1;
+    let msb = (bus.get(addr + 1) >> (7 - tile_x)) & 1;
+    lsb | msb << 1
   }
   /**
    * Unimplemented: layer priority
    */
-  fn draw_obj(&mut self) {
+  fn draw_obj(&mut self, bus: &Bus) {
     let y = self.current_line;
-    let bus = self.bus.borrow();
     let lcdc = bus.get(RegHw::LCDC as u16);
     let obj_height = if lcdc >> 2 & 1 == 0 { 8 } else { 16 };
     for &Obj { x: obj_x, y: obj_y, tile_id, attr }
@@ -116,9 +152,9 @@ impl Ppu {
           let color_id = lsb | msb << 1;
           if color_id > 0 {
             let palette = if attr >> 4 & 1 == 0 {
-              self.get_palette(Palette::OBJ0)
+              Self::get_palette(bus, Palette::OBJ0)
             } else {
-              self.get_palette(Palette::OBJ1)
+              Self::get_palette(bus, Palette::OBJ1)
             };
             let color = palette[color_id as usize];
             self.framebuffer[y as usize * SCRN_X as usize + x as usize] = color;
@@ -128,3 +164,38 @@ impl Ppu {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::model::Model;
+
+  #[test]
+  fn window_line_skips_hidden_lines() {
+    let mut bus = Bus::new(vec![0; 0x8000], None, Model::default(), None).unwrap();
+    // Tile 1 is solid color 3, and only the second row of the window map uses it
+    for i in 0..16 { bus.set(0x8010 + i, 0xFF); }
+    for i in 0..32 { bus.set(0x9C20 + i, 1); }
+    bus.set(0xFF47, 0b_11_10_01_00);
+    bus.set(RegHw::WY as u16, 0);
+    bus.set(RegHw::WX as u16, 7);
+    let lcdc_window = 0b_1111_0001;
+    let mut ppu = Ppu::new();
+    bus.set(RegHw::LCDC as u16, lcdc_window);
+    for _ in 0..8 { ppu.tick(&mut bus); }
+    assert_eq!(ppu.window_line, 8);
+    bus.set(RegHw::LCDC as u16, lcdc_window & !0b_0010_0000);
+    for _ in 8..16 { ppu.tick(&mut bus); }
+    assert_eq!(ppu.window_line, 8);
+    bus.set(RegHw::LCDC as u16, lcdc_window);
+    ppu.tick(&mut bus);
+    assert_eq!(ppu.window_line, 9);
+    // Line 16 shows window line 8, not 16
+    let line_16 = &ppu.framebuffer[16 * SCRN_X as usize..17 * SCRN_X as usize];
+    assert!(line_16.iter().all(|&color| color == 3));
+    // Moving WX off screen hides the window without advancing its line either
+    bus.set(RegHw::WX as u16, SCRN_X + 7);
+    ppu.tick(&mut bus);
+    assert_eq!(ppu.window_line, 9);
+  }
+}
@@ -0,0 +1,138 @@
+/**
+ * Something that happens at a known T-state.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+  /**
+   * The PPU starts a new line.
+   */
+  PpuLine,
+  /**
+   * TIMA is reloaded from TMA and the timer interrupt requested.
+   */
+  TimerOverflow,
+  /**
+   * Clocked cartridge hardware (RTC, camera) catches up.
+   */
+  MapperClock,
+}
+
+impl Event {
+  pub const ALL: [Event; 3] = {
+    use Event::*;
+    [PpuLine, TimerOverflow, MapperClock]
+  };
+}
+
+/**
+ * Keeps the deadline of each pending event, so that time can advance straight
+ * to the earliest one instead of every component checking the clock on every
+ * M-cycle.
+ */
+#[derive(Clone)]
+pub struct Scheduler {
+  deadlines: [u64; Event::ALL.len()],
+  next: u64,
+}
+
+impl Default for Scheduler {
+  fn default() -> Self { Self::new() }
+}
+
+impl Scheduler {
+  pub fn new() -> Self {
+    Self { deadlines: [u64::MAX; Event::ALL.len()], next: u64::MAX }
+  }
+
+  /**
+   * Replaces any pending occurrence of `event`.
+   */
+  pub fn schedule(&mut self, event: Event, t_state: u64) {
+    self.deadlines[event as usize] = t_state;
+    self.update_next();
+  }
+  pub fn cancel(&mut self, event: Event) {
+    self.deadlines[event as usize] = u64::MAX;
+    self.update_next();
+  }
+
+  /**
+   * T-state of the earliest pending event, or `u64::MAX` if there's none.
+   */
+  pub fn next_deadline(&self) -> u64 { self.next }
+
+  /**
+   * Removes and returns the earliest event due at or before `t_state`, along
+   * with its deadline.
+   */
+  pub fn pop(&mut self, t_state: u64) -> Option<(Event, u64)> {
+    if self.next > t_state { return None; }
+    let event = Event::ALL.into_iter().min_by_key(|&event| self.deadlines[event as usize])?;
+    let deadline = self.deadlines[event as usize];
+    self.deadlines[event as usize] = u64::MAX;
+    self.update_next();
+    Some((event, deadline))
+  }
+
+  fn update_next(&mut self) {
+    self.next = self.deadlines.iter().copied().min().unwrap_or(u64::MAX);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Event, Scheduler};
+  use crate::core::bus::timer::Timer;
+
+  #[test]
+  fn pops_in_deadline_order() {
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(Event::MapperClock, 300);
+    scheduler.schedule(Event::PpuLine, 100);
+    scheduler.schedule(Event::TimerOverflow, 200);
+    assert_eq!(scheduler.next_deadline(), 100);
+    assert_eq!(scheduler.pop(99), None);
+    assert_eq!(scheduler.pop(250), Some((Event::PpuLine, 100)));
+    assert_eq!(scheduler.pop(250), Some((Event::TimerOverflow, 200)));
+    assert_eq!(scheduler.pop(250), None);
+    assert_eq!(scheduler.next_deadline(), 300);
+  }
+
+  #[test]
+  fn schedule_moves_pending_event() {
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(Event::TimerOverflow, 100);
+    scheduler.schedule(Event::PpuLine, 456);
+    scheduler.schedule(Event::TimerOverflow, 1000);
+    assert_eq!(scheduler.next_deadline(), 456);
+    assert_eq!(scheduler.pop(500), Some((Event::PpuLine, 456)));
+    assert_eq!(scheduler.pop(500), None);
+    scheduler.cancel(Event::TimerOverflow);
+    assert_eq!(scheduler.next_deadline(), u64::MAX);
+  }
+
+  #[test]
+  fn timer_overflow_event() {
+    // TIMA counts every 16 T-states from 0xF0 and reloads with 0x80
+    let mut timer = Timer::new();
+    timer.set(3, 0b101, 0);
+    timer.set(2, 0x80, 0);
+    timer.set(1, 0xF0, 0);
+    let mut scheduler = Scheduler::new();
+    let overflow = timer.next_overflow().unwrap();
+    // Reloaded the M-cycle after TIMA wraps
+    assert_eq!(overflow, 260);
+    scheduler.schedule(Event::TimerOverflow, overflow);
+    assert_eq!(scheduler.pop(overflow - 4), None);
+    timer.sync(overflow - 4);
+    assert!(!timer.overflow);
+    assert_eq!(scheduler.pop(overflow + 100), Some((Event::TimerOverflow, overflow)));
+    timer.sync(overflow);
+    assert!(timer.overflow);
+    assert_eq!(timer.get(1, overflow), 0x80);
+    // Counting 4 times slower pushes the next one back
+    assert_eq!(timer.next_overflow(), Some(overflow + 0x80 * 16));
+    timer.set(3, 0b110, overflow);
+    assert_eq!(timer.next_overflow(), Some(overflow + 0x80 * 64));
+  }
+}
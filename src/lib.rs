@@ -0,0 +1,14 @@
+pub mod camera;
+pub mod core;
+pub mod save;
+pub mod triple_buffer;
+
+pub use crate::core::{
+  bus::{Bus, gamepad::Gamepad, timer::Timer},
+  cartridge::Cartridge,
+  cpu::Cpu,
+  emu::{Emu, EmuOptions, RunResult},
+  error::EmuError,
+  model::Model,
+  ppu::Ppu,
+};
@@ -1,22 +1,45 @@
 #![windows_subsystem = "console"]
 
-use std::{env, fs::{create_dir_all, File}, io, path::Path, time::Duration};
+use std::{collections::VecDeque, env, error::Error, fs::{self, File}, io, path::Path, process,
+  sync::mpsc::{self, Receiver, TryRecvError}, thread, time::Duration};
 
 use crossterm::{ExecutableCommand, cursor::MoveTo};
-use memmap2::{Mmap, MmapMut};
 use sdl2::{pixels::PixelFormatEnum, event::Event, rect::Rect, keyboard::Keycode};
 use stopwatch::Stopwatch;
 
-use crate::core::{emu::{Emu, RegHw}, cpu::{Reg16, Reg, Inst}};
-
-mod core;
+use gamecrab::{Cartridge, Emu, EmuError, EmuOptions, Gamepad, camera, save::SaveFile,
+  triple_buffer::{triple_buffer, Writer},
+  core::{cartridge::CgbSupport, emu::RegHw, cpu::Reg16, ppu::FRAMEBUFFER_SIZE}};
 
 const FREQ: f64 = 4194304.0 / 1.0;
-const FAST_FORWARD_FREQ: f64 = FREQ * 2.0;
+/**
+ * Fast-forward isn't throttled. It runs this many T-states, a frame's worth,
+ * between checks for input.
+ */
+const FAST_FORWARD_SLICE: u64 = 70224;
 const PRINT_DEBUG: bool = true;
 // const PRINT_INTERVAL: u32 = 1;
 const PRINT_INTERVAL: u32 = FREQ as u32 / 240;
 const DEBUG_START_FAST_FORWARD_TO: u64 = 0;
+/**
+ * T-states emulated per wake-up of the emulation thread, about a millisecond's
+ * worth.
+ */
+const SLICE: u64 = FREQ as u64 / 1000;
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/**
+ * Emulated time a test ROM gets to finish, in T-states.
+ */
+const TEST_TIMEOUT: u64 = 30 * FREQ as u64;
+/**
+ * B, C, D, E, H and L of a passing mooneye-gb test.
+ */
+const TEST_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/**
+ * Default keys tilting MBC7 cartridges up, down, left and right. Otherwise the
+ * mouse position relative to the window center does.
+ */
+const TILT_KEYS: [Keycode; 4] = [Keycode::I, Keycode::K, Keycode::J, Keycode::L];
 
 const PALETTE: &[(u8, u8, u8)] = &[
   (255, 255, 255),
@@ -25,144 +48,407 @@ const PALETTE: &[(u8, u8, u8)] = &[
   (  0,   0,   0),
 ];
 
+/**
+ * Sent from the SDL thread to the emulation thread.
+ */
+#[derive(Clone, Copy)]
+enum Input {
+  Button(Button, bool),
+  Tilt(f32, f32),
+  FastForward(bool),
+}
+
+#[derive(Clone, Copy)]
+enum Button { Up, Down, Left, Right, A, B, Select, Start }
+
+/**
+ * `time` is when the input happened, on the uptime stopwatch shared by both
+ * threads.
+ */
+struct TimedInput {
+  time: Duration,
+  input: Input,
+}
+
 fn main() {
-  let sdl = sdl2::init().unwrap();
-  let sdl_video = sdl.video().unwrap();
+  if let Err(err) = run() {
+    eprintln!("{}", err);
+    process::exit(1);
+  }
+}
+
+/**
+ * Options of `gamecrab <rom> [options]`.
+ */
+#[derive(Default)]
+struct PlayOptions {
+  /**
+   * PNG or PGM file seen by the Game Boy Camera, instead of a test pattern.
+   */
+  camera: Option<String>,
+  /**
+   * Overrides `TILT_KEYS`.
+   */
+  tilt_keys: Option<[Keycode; 4]>,
+  emu: EmuOptions,
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+  let args: Vec<String> = env::args().skip(1).collect();
+  match args.first().map(String::as_str) {
+    Some("info") => info(args.get(1).ok_or("Usage: gamecrab info <rom>")?),
+    Some("test") => test(&args[1..]),
+    Some(rom_path) => play(rom_path, parse_play_options(&args[1..])?),
+    None => Err("Please provide a ROM path at argument 1.".into()),
+  }
+}
+
+fn parse_play_options(args: &[String]) -> Result<PlayOptions, Box<dyn Error>> {
+  let mut options = PlayOptions::default();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+    match arg.as_str() {
+      "--camera" => options.camera = Some(value()?.clone()),
+      "--tilt-keys" => options.tilt_keys = Some(parse_tilt_keys(value()?)?),
+      "--mapper" => options.emu.mapper = Some(value()?.parse()?),
+      "--boot-rom" => options.emu.boot_rom = Some(fs::read(value()?)?),
+      "--model" => options.emu.model = value()?.parse()?,
+      _ => return Err(format!("Unknown option {}", arg).into()),
+    }
+  }
+  Ok(options)
+}
+
+/**
+ * Four comma-separated SDL key names, e.g. `W,S,A,D`.
+ */
+fn parse_tilt_keys(value: &str) -> Result<[Keycode; 4], Box<dyn Error>> {
+  let keys = value.split(',')
+    .map(|name| Keycode::from_name(name.trim()).ok_or(format!("Unknown key {}", name)))
+    .collect::<Result<Vec<_>, _>>()?;
+  keys.try_into().map_err(|_| "Tilt keys must be up, down, left and right".into())
+}
+
+fn info(rom_path: &str) -> Result<(), Box<dyn Error>> {
+  let rom = fs::read(rom_path)?;
+  let cart = Cartridge::parse(&rom)?;
+  let yes_no = |value: bool| if value { "Yes" } else { "No" };
+  let ok_bad = |value: bool| if value { "OK" } else { "BAD" };
+  println!("Title:           {}", cart.title);
+  println!("Manufacturer:    {}", cart.manufacturer_code.as_deref().unwrap_or("-"));
+  println!("CGB:             {}", match cart.cgb {
+    CgbSupport::None => "No",
+    CgbSupport::Enhanced => "Enhanced",
+    CgbSupport::Only => "Only",
+  });
+  println!("SGB:             {}", yes_no(cart.sgb));
+  println!("Licensee:        {}", cart.licensee);
+  match (cart.mapper, cart.bootleg_rule) {
+    (Some(mapper), Some(rule)) =>
+      println!("Cartridge type:  {:02X} (detected {} by {})", cart.cart_type, mapper, rule),
+    (Some(mapper), None) => println!("Cartridge type:  {:02X} ({})", cart.cart_type, mapper),
+    (None, _) => println!("Cartridge type:  {:02X} (Unknown)", cart.cart_type),
+  }
+  println!("ROM size:        {} KiB", cart.rom_size / 1024);
+  println!("RAM size:        {} KiB", cart.ram_size / 1024);
+  println!("Battery:         {}", yes_no(cart.battery));
+  println!("RTC:             {}", yes_no(cart.rtc));
+  println!("Rumble:          {}", yes_no(cart.rumble));
+  println!("Version:         {}", cart.version);
+  println!("Header checksum: {:02X} ({})", cart.header_checksum, ok_bad(cart.header_checksum_ok));
+  println!("Global checksum: {:04X} ({})", cart.global_checksum, ok_bad(cart.global_checksum_ok));
+  Ok(())
+}
+
+/**
+ * Runs test ROMs without a window. mooneye-gb ones signal the end with
+ * `LD B,B`, then the registers tell whether they passed. blargg's print
+ * "Passed" or "Failed" over serial.
+ */
+fn test(rom_paths: &[String]) -> Result<(), Box<dyn Error>> {
+  if rom_paths.is_empty() { return Err("Usage: gamecrab test <rom>...".into()); }
+  let mut failed = 0;
+  for rom_path in rom_paths {
+    match run_test(rom_path) {
+      Ok(()) => println!("PASS {}", rom_path),
+      Err(err) => {
+        println!("FAIL {}: {}", rom_path, err);
+        failed += 1;
+      }
+    }
+  }
+  if failed > 0 {
+    return Err(format!("{} of {} tests failed.", failed, rom_paths.len()).into());
+  }
+  Ok(())
+}
+
+fn run_test(rom_path: &str) -> Result<(), Box<dyn Error>> {
+  let mut emu = Emu::new(fs::read(rom_path)?, None)?;
+  emu.bus.serial.output = Some(vec![]);
+  let mut output_len = 0;
+  while emu.bus.get(emu.cpu.get_reg_16(Reg16::PC)) != 0x40 {
+    let output = emu.bus.serial.output.as_deref().unwrap_or_default();
+    if output.len() != output_len {
+      output_len = output.len();
+      if let Some(result) = serial_result(output) { return result; }
+    }
+    if emu.clock.get_t_state() >= TEST_TIMEOUT {
+      let output = one_line(output);
+      if output.is_empty() { return Err("Timed out".into()); }
+      return Err(format!("Timed out, serial output: {}", output).into());
+    }
+    emu.tick()?;
+  }
+  let regs: Vec<u8> = [Reg16::BC, Reg16::DE, Reg16::HL].into_iter()
+    .flat_map(|reg| emu.cpu.get_reg_16(reg).to_be_bytes())
+    .collect();
+  if regs != TEST_PASS {
+    return Err(format!("B, C, D, E, H, L = {:?}", regs).into());
+  }
+  Ok(())
+}
+
+/**
+ * Result of a blargg test once it has printed the whole line saying whether it
+ * passed.
+ */
+fn serial_result(output: &[u8]) -> Option<Result<(), Box<dyn Error>>> {
+  let text = String::from_utf8_lossy(output);
+  let line_end = |idx: usize| text[idx..].contains('\n');
+  match (text.find("Passed"), text.find("Failed")) {
+    (_, Some(idx)) if line_end(idx) => Some(Err(one_line(output).into())),
+    (Some(idx), None) if line_end(idx) => Some(Ok(())),
+    _ => None,
+  }
+}
+
+/**
+ * Serial output with its lines joined, to fit in a test report.
+ */
+fn one_line(output: &[u8]) -> String {
+  String::from_utf8_lossy(output).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn play(rom_path: &str, options: PlayOptions) -> Result<(), Box<dyn Error>> {
+  let rom = fs::read(rom_path)?;
+  let cart = Cartridge::parse(&rom)?;
+  if let (Some(mapper), Some(rule), None) = (cart.mapper, cart.bootleg_rule, options.emu.mapper) {
+    eprintln!("Detected {} by {}", mapper, rule);
+  }
+  let mut save_file = if cart.battery {
+    let file_name = Path::new(rom_path).file_stem().unwrap_or_default().to_string_lossy();
+    Some(SaveFile::new(format!("save/{}.sav", file_name)))
+  } else {
+    None
+  };
+  let sram = match &mut save_file {
+    Some(save_file) => save_file.read()?,
+    None => None,
+  };
+  let mut emu = Emu::with_options(rom, sram, options.emu)?;
+  emu.set_camera_image(&match &options.camera {
+    Some(path) => camera::load_image(path)?,
+    None => camera::test_pattern(),
+  });
+  emu.cpu.trace_log = Some(Box::new(File::create("log.txt")?));
+  let sdl = sdl2::init()?;
+  let sdl_video = sdl.video()?;
+  let sdl_timer = sdl.timer()?;
   let window = sdl_video
     .window("gamecrab", 640, 576)
     .opengl()
-    .build()
-    .unwrap();
+    .build()?;
   let mut canvas = window
     .into_canvas()
-    .index(find_sdl_gl_driver().unwrap())
+    .index(find_sdl_gl_driver().ok_or("OpenGL render driver not found.")?)
     .present_vsync()
-    .build()
-    .unwrap();
+    .build()?;
   let texture_creator = canvas.texture_creator();
   let mut texture = texture_creator
-    .create_texture_streaming(PixelFormatEnum::RGB24, 160, 144).unwrap();
-  let rom_path = env::args().nth(1)
-    .expect("Please provide a ROM path at argument 1.");
-  let rom_file = File::open(&rom_path)
-    .expect("Cannot open file.");
-  let rom = unsafe { Mmap::map(&rom_file).unwrap() };
-  let sram_size = [0, 0, 0x2000, 0x8000, 0x20000, 0x10000][rom[0x149] as usize];
-  let sram_file = if sram_size > 0 {
-    create_dir_all("save").unwrap();
-    let file_name = Path::new(&rom_path).file_stem().unwrap().to_str().unwrap();
-    let file = File::options()
-      .read(true).write(true).create(true)
-      .open(format!("save/{}.sav", file_name))
-      .unwrap();
-    file.set_len(sram_size as u64).unwrap();
-    Some(file)
-  } else {
-    None
-  };
-  let sram = match sram_file {
-    Some(file) => unsafe { Some(MmapMut::map_mut(&file).unwrap()) },
-    None => None,
-  };
-  let mut emu = Emu::new(rom, sram);
+    .create_texture_streaming(PixelFormatEnum::RGB24, 160, 144)?;
   let uptime = Stopwatch::start_new();
-  let mut last_frame_time = Duration::default();
-  let mut freq = FREQ;
-  let mut print_debug = PRINT_DEBUG;
-  let mut event_pump = sdl.event_pump().unwrap();
-  let mut count_to_next_print = 0;
-  'running: loop {
+  let (frame_writer, mut frames) = triple_buffer([0; FRAMEBUFFER_SIZE]);
+  let (input_sender, inputs) = mpsc::channel();
+  let emulation = thread::spawn(move || emulate(emu, save_file, uptime, inputs, frame_writer));
+  let mut event_pump = sdl.event_pump()?;
+  let tilt_keys = options.tilt_keys.unwrap_or(TILT_KEYS);
+  let mut tilt_held = [false; 4];
+  let mut mouse_tilt = (0.0, 0.0);
+  let mut tilt = (0.0, 0.0);
+  'running: while !emulation.is_finished() {
+    let now = uptime.elapsed();
+    let now_ticks = sdl_timer.ticks();
     for event in event_pump.poll_iter() {
+      let ago = Duration::from_millis(now_ticks.saturating_sub(event.get_timestamp()) as u64);
+      let send = |input| { _ = input_sender.send(TimedInput { time: now.saturating_sub(ago), input }); };
       match event {
         Event::Quit { .. } => break 'running,
-        Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
-          Keycode::Escape => break 'running,
-          Keycode::Up => emu.bus.borrow_mut().gamepad.up = true,
-          Keycode::Down => emu.bus.borrow_mut().gamepad.down = true,
-          Keycode::Left => emu.bus.borrow_mut().gamepad.left = true,
-          Keycode::Right => emu.bus.borrow_mut().gamepad.right = true,
-          Keycode::A | Keycode::Home => emu.bus.borrow_mut().gamepad.select = true,
-          Keycode::S | Keycode::End => emu.bus.borrow_mut().gamepad.start = true,
-          Keycode::Z | Keycode::PageUp => emu.bus.borrow_mut().gamepad.a = true,
-          Keycode::X | Keycode::PageDown => emu.bus.borrow_mut().gamepad.b = true,
-          Keycode::F => {
-            print_debug = false;
-            freq = FAST_FORWARD_FREQ;
-          }
-          _ => {}
+        Event::KeyDown { keycode: Some(keycode), .. } | Event::KeyUp { keycode: Some(keycode), .. }
+        if tilt_keys.contains(&keycode) => {
+          let i = tilt_keys.iter().position(|&key| key == keycode).unwrap();
+          tilt_held[i] = matches!(event, Event::KeyDown { .. });
         }
-        Event::KeyUp { keycode: Some(keycode), .. } => match keycode {
-          Keycode::Up => emu.bus.borrow_mut().gamepad.up = false,
-          Keycode::Down => emu.bus.borrow_mut().gamepad.down = false,
-          Keycode::Left => emu.bus.borrow_mut().gamepad.left = false,
-          Keycode::Right => emu.bus.borrow_mut().gamepad.right = false,
-          Keycode::A | Keycode::Home => emu.bus.borrow_mut().gamepad.select = false,
-          Keycode::S | Keycode::End => emu.bus.borrow_mut().gamepad.start = false,
-          Keycode::Z | Keycode::PageUp => emu.bus.borrow_mut().gamepad.a = false,
-          Keycode::X | Keycode::PageDown => emu.bus.borrow_mut().gamepad.b = false,
-          Keycode::F => {
-            print_debug = PRINT_DEBUG;
-            freq = FREQ;
-          }
-          _ => {}
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+        Event::KeyDown { keycode: Some(Keycode::F), repeat: false, .. } => send(Input::FastForward(true)),
+        Event::KeyUp { keycode: Some(Keycode::F), .. } => send(Input::FastForward(false)),
+        Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => if let Some(button) = button(keycode) {
+          send(Input::Button(button, true));
         }
-        Event::MouseButtonDown { .. } => {
-          print_debug = false;
-          freq = FAST_FORWARD_FREQ;
+        Event::KeyUp { keycode: Some(keycode), .. } => if let Some(button) = button(keycode) {
+          send(Input::Button(button, false));
         }
-        Event::MouseButtonUp { .. } => {
-          print_debug = PRINT_DEBUG;
-          freq = FREQ;
+        Event::MouseButtonDown { .. } => send(Input::FastForward(true)),
+        Event::MouseButtonUp { .. } => send(Input::FastForward(false)),
+        Event::MouseMotion { x, y, .. } => {
+          mouse_tilt = ((x as f32 - 320.0) / 320.0, (y as f32 - 288.0) / 288.0);
         }
         _ => {}
       }
     }
+    let new_tilt = if tilt_held.contains(&true) {
+      let axis = |neg: bool, pos: bool| pos as u8 as f32 - neg as u8 as f32;
+      (axis(tilt_held[2], tilt_held[3]), axis(tilt_held[0], tilt_held[1]))
+    } else {
+      mouse_tilt
+    };
+    if new_tilt != tilt {
+      tilt = new_tilt;
+      _ = input_sender.send(TimedInput { time: now, input: Input::Tilt(tilt.0, tilt.1) });
+    }
+    let framebuffer = frames.read();
+    texture.with_lock(None, |buffer, _| {
+      for i in 0..(160 * 144) {
+        let (r, g, b) = PALETTE[framebuffer[i] as usize];
+        buffer[i * 3 + 0] = r;
+        buffer[i * 3 + 1] = g;
+        buffer[i * 3 + 2] = b;
+      }
+    })?;
+    canvas.copy(&texture, None, Some(Rect::new(0, 0, 640, 576)))?;
+    canvas.present();
+  }
+  drop(input_sender);
+  Ok(emulation.join().map_err(|_| "Emulation thread panicked.")??)
+}
+
+/**
+ * Runs `emu` until the SDL thread hangs up or emulation fails, then writes the
+ * battery save either way.
+ */
+fn emulate(
+  mut emu: Emu,
+  mut save_file: Option<SaveFile>,
+  uptime: Stopwatch,
+  inputs: Receiver<TimedInput>,
+  frames: Writer<[u8; FRAMEBUFFER_SIZE]>,
+) -> Result<(), EmuError> {
+  let result = run_real_time(&mut emu, &mut save_file, uptime, inputs, frames);
+  let saved = match (&mut save_file, emu.sram()) {
+    (Some(save_file), Some(sram)) => save_file.write(&sram),
+    _ => Ok(()),
+  };
+  result.and(saved)
+}
+
+/**
+ * Runs `emu` in real time, publishing every completed frame. Each input is
+ * applied at the emulated cycle matching when it happened, keeping the spacing
+ * between inputs that arrive together, so a press shorter than a video frame
+ * still reaches the game.
+ */
+fn run_real_time(
+  emu: &mut Emu,
+  save_file: &mut Option<SaveFile>,
+  uptime: Stopwatch,
+  inputs: Receiver<TimedInput>,
+  mut frames: Writer<[u8; FRAMEBUFFER_SIZE]>,
+) -> Result<(), EmuError> {
+  emu.run_cycles(DEBUG_START_FAST_FORWARD_TO)?;
+  let mut last_time = uptime.elapsed();
+  let mut last_save_time = last_time;
+  let mut fast_forward = false;
+  let mut print_debug = PRINT_DEBUG;
+  let mut count_to_next_print = 0;
+  let mut pending = VecDeque::new();
+  // The T-state emulation should reach by `last_time`
+  let mut target_t_state = emu.clock.get_t_state();
+  let mut last_input = (last_time, target_t_state);
+  loop {
     let current_time = uptime.elapsed();
-    let delta_time = current_time - last_frame_time;
-    last_frame_time = current_time;
-    let t_state = emu.clock.borrow().get_t_state();
-    let target_t_state = t_state + (delta_time.as_secs_f64() * freq) as u64;
-    while emu.clock.borrow().get_t_state()
-        < target_t_state + DEBUG_START_FAST_FORWARD_TO {
-      if count_to_next_print <= 0 {
+    let t_states_since = |time: Duration, since: Duration|
+      (time.saturating_sub(since).as_secs_f64() * FREQ) as u64;
+    loop {
+      match inputs.try_recv() {
+        Ok(TimedInput { time, input }) => {
+          let at = (target_t_state + t_states_since(time, last_time))
+            .max(last_input.1 + t_states_since(time, last_input.0));
+          last_input = (time, at);
+          pending.push_back((at, input));
+        }
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => return Ok(()),
+      }
+    }
+    target_t_state = if fast_forward {
+      emu.clock.get_t_state() + FAST_FORWARD_SLICE
+    } else {
+      target_t_state + t_states_since(current_time, last_time)
+    };
+    last_time = current_time;
+    while emu.clock.get_t_state() < target_t_state {
+      while let Some(&(at, input)) = pending.front() {
+        if at > emu.clock.get_t_state() { break; }
+        pending.pop_front();
+        match input {
+          Input::Button(button, pressed) => *gamepad_button(&mut emu.bus.gamepad, button) = pressed,
+          Input::Tilt(x, y) => emu.set_tilt(x, y),
+          Input::FastForward(on) => {
+            print_debug = PRINT_DEBUG && !on;
+            fast_forward = on;
+          }
+        }
+      }
+      if count_to_next_print == 0 {
         count_to_next_print = PRINT_INTERVAL;
         _ = io::stdout().execute(MoveTo(0, 0));
         println!("Clk={}, PC={:04X}, SP={:04X}",
-          emu.clock.borrow().get_t_state(),
+          emu.clock.get_t_state(),
           emu.cpu.get_reg_16(Reg16::PC),
           emu.cpu.get_reg_16(Reg16::SP),
         );
-        if print_debug && t_state >= DEBUG_START_FAST_FORWARD_TO {
+        if print_debug {
           println!("AF={:04X}, BC={:04X}, DE={:04X}, HL={:04X}, [HL]={:02X}",
             emu.cpu.get_reg_16(Reg16::AF),
             emu.cpu.get_reg_16(Reg16::BC),
             emu.cpu.get_reg_16(Reg16::DE),
             emu.cpu.get_reg_16(Reg16::HL),
-            emu.cpu.get_reg(Reg::AddrHL),
+            emu.bus.get(emu.cpu.get_reg_16(Reg16::HL)),
           );
           println!("LCDC={:08b}, LY={:03}",
-            emu.bus.borrow().get(RegHw::LCDC as u16),
+            emu.bus.get(RegHw::LCDC as u16),
             emu.ppu.current_line,
           );
           println!("ROM={}, SRAM={}",
-            emu.bus.borrow().rom_bank,
-            emu.bus.borrow().sram_bank,
+            emu.bus.mapper.rom_bank(),
+            emu.bus.mapper.ram_bank(),
           );
           print!("Stack   "); {
             let sp = emu.cpu.get_reg_16(Reg16::SP);
-            let bus = emu.bus.borrow();
+            let bus = &emu.bus;
             for addr in sp..(sp + 16) {
               print!("{:02X} ", bus.get(addr));
             }
           }
           // print!("... \nTiles   "); {
-          //   let bus = emu.bus.borrow();
+          //   let bus = &emu.bus;
           //   for i in 0x8000..0x801B {
           //     print!("{:02X} ", bus.get(i));
           //   }
           // }
           // println!("...\n\nMap 0 - Visible Area"); {
-          //   let bus = emu.bus.borrow();
+          //   let bus = &emu.bus;
           //   for y in 0..18 {
           //     for x in 0..20 {
           //       print!("{:02X} ", bus.get(0x9800 + y * 32 + x));
@@ -180,18 +466,50 @@ fn main() {
         }
       }
       count_to_next_print -= 1;
-      emu.tick();
+      if emu.tick()? {
+        frames.back().copy_from_slice(&emu.ppu.framebuffer);
+        frames.publish();
+      }
     }
-    texture.with_lock(None, |buffer, _| {
-      for i in 0..(160 * 144) {
-        let (r, g, b) = PALETTE[emu.ppu.framebuffer[i] as usize];
-        buffer[i * 3 + 0] = r;
-        buffer[i * 3 + 1] = g;
-        buffer[i * 3 + 2] = b;
+    if current_time - last_save_time >= SAVE_INTERVAL {
+      last_save_time = current_time;
+      if let (Some(save_file), Some(sram)) = (save_file.as_mut(), emu.sram()) {
+        save_file.write(&sram)?;
       }
-    }).unwrap();
-    canvas.copy(&texture, None, Some(Rect::new(0, 0, 640, 576))).unwrap();
-    canvas.present();
+    }
+    if !fast_forward {
+      // Until the next slice is due
+      let ahead = emu.clock.get_t_state() + SLICE - target_t_state;
+      let wake_time = current_time + Duration::from_secs_f64(ahead as f64 / FREQ);
+      thread::sleep(wake_time.saturating_sub(uptime.elapsed()));
+    }
+  }
+}
+
+fn gamepad_button(gamepad: &mut Gamepad, button: Button) -> &mut bool {
+  match button {
+    Button::Up => &mut gamepad.up,
+    Button::Down => &mut gamepad.down,
+    Button::Left => &mut gamepad.left,
+    Button::Right => &mut gamepad.right,
+    Button::A => &mut gamepad.a,
+    Button::B => &mut gamepad.b,
+    Button::Select => &mut gamepad.select,
+    Button::Start => &mut gamepad.start,
+  }
+}
+
+fn button(keycode: Keycode) -> Option<Button> {
+  match keycode {
+    Keycode::Up => Some(Button::Up),
+    Keycode::Down => Some(Button::Down),
+    Keycode::Left => Some(Button::Left),
+    Keycode::Right => Some(Button::Right),
+    Keycode::A | Keycode::Home => Some(Button::Select),
+    Keycode::S | Keycode::End => Some(Button::Start),
+    Keycode::Z | Keycode::PageUp => Some(Button::A),
+    Keycode::X | Keycode::PageDown => Some(Button::B),
+    _ => None,
   }
 }
 
@@ -0,0 +1,48 @@
+use std::{fs::{self, File}, io::{ErrorKind, Write}, path::PathBuf};
+
+use crate::core::error::EmuError;
+
+/**
+ * Battery save on disk. Writes go to a temporary file that then replaces the
+ * save, so a crash mid-write never leaves a torn `.sav` behind.
+ */
+pub struct SaveFile {
+  path: PathBuf,
+  last_written: Option<Vec<u8>>,
+}
+
+impl SaveFile {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: path.into(), last_written: None }
+  }
+
+  /**
+   * `None` if there's no save yet.
+   */
+  pub fn read(&mut self) -> Result<Option<Vec<u8>>, EmuError> {
+    match fs::read(&self.path) {
+      Ok(data) => {
+        self.last_written = Some(data.clone());
+        Ok(Some(data))
+      }
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  /**
+   * Does nothing if `data` is what's already on disk.
+   */
+  pub fn write(&mut self, data: &[u8]) -> Result<(), EmuError> {
+    if self.last_written.as_deref() == Some(data) { return Ok(()); }
+    if let Some(dir) = self.path.parent() { fs::create_dir_all(dir)?; }
+    let mut tmp_path = self.path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(data)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, &self.path)?;
+    self.last_written = Some(data.to_vec());
+    Ok(())
+  }
+}
@@ -0,0 +1,57 @@
+use std::{mem, sync::{Arc, Mutex}};
+
+/**
+ * Hands the latest value from one thread to another without either waiting on
+ * the other. The writer fills its back buffer and publishes it; the reader
+ * picks up whatever was published last, skipping any it missed. The lock is
+ * only held to swap two buffers.
+ */
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+  let middle = Arc::new(Mutex::new(Middle { buffer: initial.clone(), fresh: false }));
+  (
+    Writer { back: initial.clone(), middle: middle.clone() },
+    Reader { front: initial, middle },
+  )
+}
+
+struct Middle<T> {
+  buffer: T,
+  /**
+   * Published since the reader last looked.
+   */
+  fresh: bool,
+}
+
+pub struct Writer<T> {
+  back: T,
+  middle: Arc<Mutex<Middle<T>>>,
+}
+
+impl<T> Writer<T> {
+  pub fn back(&mut self) -> &mut T { &mut self.back }
+  pub fn publish(&mut self) {
+    let mut middle = self.middle.lock().unwrap();
+    mem::swap(&mut middle.buffer, &mut self.back);
+    middle.fresh = true;
+  }
+}
+
+pub struct Reader<T> {
+  front: T,
+  middle: Arc<Mutex<Middle<T>>>,
+}
+
+impl<T> Reader<T> {
+  /**
+   * The latest published value, or the same one as last time if nothing new
+   * was published.
+   */
+  pub fn read(&mut self) -> &T {
+    let mut middle = self.middle.lock().unwrap();
+    if middle.fresh {
+      mem::swap(&mut middle.buffer, &mut self.front);
+      middle.fresh = false;
+    }
+    &self.front
+  }
+}